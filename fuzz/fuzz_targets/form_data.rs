@@ -0,0 +1,12 @@
+#![no_main]
+use https_server_app::infra::http::form_data::FormData;
+use libfuzzer_sys::fuzz_target;
+
+// the first line is the Content-Type header, the rest is the body
+fuzz_target!(|data: &[u8]| {
+    let split = data.iter().position(|byte| *byte == b'\n').unwrap_or(data.len());
+    let content_type = String::from_utf8_lossy(&data[..split]);
+    if let Ok(boundary) = FormData::boundary(&content_type) {
+        let _ = FormData::parse(boundary.as_bytes(), &data[(split + 1).min(data.len())..]);
+    }
+});
@@ -0,0 +1,44 @@
+#![no_main]
+use std::{
+    cell::RefCell,
+    io::{self, Cursor, Read, Write},
+    rc::Rc,
+    sync::Arc,
+};
+
+use https_server_app::infra::http::{message, status};
+use libfuzzer_sys::fuzz_target;
+
+struct Connection {
+    input: Cursor<Vec<u8>>,
+}
+
+impl Read for Connection {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.input.read(buf)
+    }
+}
+
+impl message::ReadTimeout for Connection {}
+
+impl Write for Connection {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    for strict in [false, true] {
+        let on_data: message::HandleFn = Box::new(Arc::new(|_| {
+            message::Response::with_text(status::OK, "ok")
+        }));
+        let connection = Rc::new(RefCell::new(Connection {
+            input: Cursor::new(data.to_vec()),
+        }));
+        // malformed input may end in an error, only panics are findings
+        message::consume(connection, &on_data, None, strict).ok();
+    }
+});
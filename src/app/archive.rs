@@ -0,0 +1,513 @@
+use flate2::{write::GzEncoder, Compression, CrcReader};
+use std::{
+    fs::{self, File},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use super::middleware::{is_hidden, StaticOptions};
+use crate::infra::http::{
+    date, header,
+    message::{BodyStream, HttpMessage, Response},
+    status,
+};
+
+/// the classic zip format counts entries and offsets in 16 and 32 bits
+const ZIP_MAX_ENTRIES: usize = 0xFFFF;
+const ZIP_MAX_OFFSET: u64 = 0xFFFF_FFFF;
+
+const TAR_BLOCK_SIZE: usize = 512;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// stored without compression, so no temp file is needed to learn the sizes
+    Zip,
+    TarGz,
+}
+
+impl Format {
+    /// The format asked for by `?archive=zip` or `?archive=tar.gz`
+    pub fn from_query(value: &str) -> Option<Format> {
+        match value.to_ascii_lowercase().as_str() {
+            "" | "zip" => Some(Format::Zip),
+            "tar.gz" | "tgz" => Some(Format::TarGz),
+            _ => None,
+        }
+    }
+    fn extension(&self) -> &'static str {
+        match self {
+            Format::Zip => "zip",
+            Format::TarGz => "tar.gz",
+        }
+    }
+    fn content_type(&self) -> &'static str {
+        match self {
+            Format::Zip => "application/zip",
+            Format::TarGz => "application/gzip",
+        }
+    }
+}
+
+struct Entry {
+    path: PathBuf,
+    /// relative to the archived directory, with `/` separators and a trailing `/` for directories
+    name: String,
+    is_dir: bool,
+    size: u64,
+    mode: u32,
+    modified: SystemTime,
+}
+
+#[cfg(unix)]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn file_mode(metadata: &fs::Metadata) -> u32 {
+    match (metadata.is_dir(), metadata.permissions().readonly()) {
+        (true, _) => 0o755,
+        (false, true) => 0o444,
+        (false, false) => 0o644,
+    }
+}
+
+/// Lists the tree below `dir` depth first, links are skipped as they could lead out of the root
+fn collect(
+    dir: &Path,
+    prefix: &str,
+    show_hidden: bool,
+    entries: &mut Vec<Entry>,
+) -> io::Result<()> {
+    let mut children: Vec<fs::DirEntry> = fs::read_dir(dir)?.collect::<io::Result<_>>()?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+        let file_name = child.file_name();
+        let file_name = file_name.to_string_lossy();
+        if !show_hidden && is_hidden(&file_name) {
+            continue;
+        }
+        let file_type = child.file_type()?;
+        if !file_type.is_dir() && !file_type.is_file() {
+            continue;
+        }
+        let metadata = child.metadata()?;
+        let mut entry = Entry {
+            path: child.path(),
+            name: format!("{}{}", prefix, file_name),
+            is_dir: file_type.is_dir(),
+            size: if file_type.is_file() {
+                metadata.len()
+            } else {
+                0
+            },
+            mode: file_mode(&metadata),
+            modified: metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+        };
+        if entry.is_dir {
+            entry.name.push('/');
+            let prefix = entry.name.clone();
+            let path = entry.path.clone();
+            entries.push(entry);
+            collect(&path, &prefix, show_hidden, entries)?;
+        } else {
+            entries.push(entry);
+        }
+    }
+    Ok(())
+}
+
+/// Counts what goes through, zip headers need the offset of every entry
+struct CountingWriter<'a> {
+    inner: &'a mut dyn Write,
+    count: u64,
+}
+
+impl<'a> Write for CountingWriter<'a> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let size = self.inner.write(buf)?;
+        self.count += size as u64;
+        Ok(size)
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// MS-DOS time and date, as used by zip headers
+fn dos_time(time: SystemTime) -> (u16, u16) {
+    let (year, month, day, seconds) = date::to_civil(time);
+    if year < 1980 {
+        return (0, (1 << 5) | 1);
+    }
+    let year = year.min(2107) as u16;
+    let time = ((seconds / 3600) << 11) | ((seconds % 3600 / 60) << 5) | ((seconds % 60) / 2);
+    let date = ((year - 1980) << 9) | ((month as u16) << 5) | day as u16;
+    (time as u16, date)
+}
+
+/// Writes the local header with zeroed sizes, they follow the data in a data descriptor
+fn zip_local_header(writer: &mut dyn Write, entry: &Entry) -> io::Result<()> {
+    let (time, date) = dos_time(entry.modified);
+    let mut header = Vec::with_capacity(30 + entry.name.len());
+    header.extend_from_slice(&0x04034b50_u32.to_le_bytes());
+    header.extend_from_slice(&20_u16.to_le_bytes());
+    header.extend_from_slice(&zip_flags(entry).to_le_bytes());
+    // stored
+    header.extend_from_slice(&0_u16.to_le_bytes());
+    header.extend_from_slice(&time.to_le_bytes());
+    header.extend_from_slice(&date.to_le_bytes());
+    header.extend_from_slice(&[0; 12]);
+    header.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+    header.extend_from_slice(&0_u16.to_le_bytes());
+    header.extend_from_slice(entry.name.as_bytes());
+    writer.write_all(&header)
+}
+
+fn zip_flags(entry: &Entry) -> u16 {
+    // names are UTF-8, and files carry a data descriptor
+    if entry.is_dir {
+        0x0800
+    } else {
+        0x0808
+    }
+}
+
+/// Streams `entries` as a zip archive, files are stored as they are
+fn write_zip(writer: &mut dyn Write, entries: &[Entry]) -> io::Result<()> {
+    let mut writer = CountingWriter {
+        inner: writer,
+        count: 0,
+    };
+    let mut central = Vec::new();
+    let mut count: u16 = 0;
+    for entry in entries {
+        let offset = writer.count as u32;
+        let (crc, size) = if entry.is_dir {
+            zip_local_header(&mut writer, entry)?;
+            (0, 0)
+        } else {
+            // a file removed since the listing is left out
+            let file = match File::open(&entry.path) {
+                Ok(file) => file,
+                Err(_) => continue,
+            };
+            zip_local_header(&mut writer, entry)?;
+            let mut reader = CrcReader::new(file.take(entry.size));
+            let size = io::copy(&mut reader, &mut writer)? as u32;
+            let crc = reader.crc().sum();
+            let mut descriptor = Vec::with_capacity(16);
+            descriptor.extend_from_slice(&0x08074b50_u32.to_le_bytes());
+            descriptor.extend_from_slice(&crc.to_le_bytes());
+            descriptor.extend_from_slice(&size.to_le_bytes());
+            descriptor.extend_from_slice(&size.to_le_bytes());
+            writer.write_all(&descriptor)?;
+            (crc, size)
+        };
+        let (time, date) = dos_time(entry.modified);
+        let file_type = if entry.is_dir { 0o040000 } else { 0o100000 };
+        let external = ((file_type | entry.mode) << 16) | if entry.is_dir { 0x10 } else { 0 };
+        central.extend_from_slice(&0x02014b50_u32.to_le_bytes());
+        // made by unix, so the mode in the external attributes is used
+        central.extend_from_slice(&((3 << 8) | 20_u16).to_le_bytes());
+        central.extend_from_slice(&20_u16.to_le_bytes());
+        central.extend_from_slice(&zip_flags(entry).to_le_bytes());
+        central.extend_from_slice(&0_u16.to_le_bytes());
+        central.extend_from_slice(&time.to_le_bytes());
+        central.extend_from_slice(&date.to_le_bytes());
+        central.extend_from_slice(&crc.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&size.to_le_bytes());
+        central.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+        central.extend_from_slice(&[0; 8]);
+        central.extend_from_slice(&external.to_le_bytes());
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(entry.name.as_bytes());
+        count += 1;
+    }
+    let offset = writer.count as u32;
+    let mut end = Vec::with_capacity(22);
+    end.extend_from_slice(&0x06054b50_u32.to_le_bytes());
+    end.extend_from_slice(&[0; 4]);
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&count.to_le_bytes());
+    end.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    end.extend_from_slice(&offset.to_le_bytes());
+    end.extend_from_slice(&0_u16.to_le_bytes());
+    writer.write_all(&central)?;
+    writer.write_all(&end)
+}
+
+/// The size of the zip `write_zip` produces, none of it is compressed
+fn zip_size(entries: &[Entry]) -> u64 {
+    let central: u64 = entries
+        .iter()
+        .map(|entry| 46 + entry.name.len() as u64)
+        .sum();
+    let local: u64 = entries
+        .iter()
+        .map(|entry| 30 + entry.name.len() as u64 + if entry.is_dir { 0 } else { entry.size + 16 })
+        .sum();
+    local + central + 22
+}
+
+/// Writes `value` as a NUL terminated octal field, or in base-256 when it does not fit
+fn tar_number(field: &mut [u8], value: u64) {
+    let digits = field.len() - 1;
+    if value < 1 << (3 * digits) {
+        let octal = format!("{:0width$o}", value, width = digits);
+        field[..digits].copy_from_slice(octal.as_bytes());
+        field[digits] = 0;
+    } else {
+        // GNU base-256, the high bit of the first byte marks it
+        let bytes = value.to_be_bytes();
+        let start = field.len() - bytes.len();
+        field[..start].fill(0);
+        field[start..].copy_from_slice(&bytes);
+        field[0] |= 0x80;
+    }
+}
+
+fn tar_header(
+    name: &[u8],
+    size: u64,
+    mode: u32,
+    modified: SystemTime,
+    kind: u8,
+) -> [u8; TAR_BLOCK_SIZE] {
+    let mut header = [0_u8; TAR_BLOCK_SIZE];
+    let length = name.len().min(100);
+    header[..length].copy_from_slice(&name[..length]);
+    tar_number(&mut header[100..108], mode as u64);
+    tar_number(&mut header[108..116], 0);
+    tar_number(&mut header[116..124], 0);
+    tar_number(&mut header[124..136], size);
+    let mtime = modified
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .map(|time| time.as_secs())
+        .unwrap_or(0);
+    tar_number(&mut header[136..148], mtime);
+    header[156] = kind;
+    header[257..265].copy_from_slice(b"ustar  \0");
+    // the checksum is computed with its own field set to spaces
+    header[148..156].fill(b' ');
+    let checksum: u32 = header.iter().map(|byte| *byte as u32).sum();
+    let checksum = format!("{:06o}\0 ", checksum);
+    header[148..156].copy_from_slice(checksum.as_bytes());
+    header
+}
+
+fn tar_padding(size: u64) -> &'static [u8] {
+    const ZEROS: [u8; TAR_BLOCK_SIZE] = [0; TAR_BLOCK_SIZE];
+    let rest = (size % TAR_BLOCK_SIZE as u64) as usize;
+    if rest == 0 {
+        &[]
+    } else {
+        &ZEROS[rest..]
+    }
+}
+
+/// Streams `entries` as a gzip compressed tar archive, long names use GNU long name entries
+fn write_tar_gz(writer: &mut dyn Write, entries: &[Entry]) -> io::Result<()> {
+    let mut writer = GzEncoder::new(writer, Compression::default());
+    for entry in entries {
+        let file = if entry.is_dir {
+            None
+        } else {
+            match File::open(&entry.path) {
+                Ok(file) => Some(file),
+                Err(_) => continue,
+            }
+        };
+        let name = entry.name.as_bytes();
+        if name.len() > 100 {
+            let size = name.len() as u64 + 1;
+            writer.write_all(&tar_header(
+                b"././@LongLink",
+                size,
+                0,
+                SystemTime::UNIX_EPOCH,
+                b'L',
+            ))?;
+            writer.write_all(name)?;
+            writer.write_all(&[0])?;
+            writer.write_all(tar_padding(size))?;
+        }
+        let kind = if entry.is_dir { b'5' } else { b'0' };
+        writer.write_all(&tar_header(
+            name,
+            entry.size,
+            entry.mode,
+            entry.modified,
+            kind,
+        ))?;
+        if let Some(file) = file {
+            // the header promised `size` bytes, a file that shrank meanwhile is padded
+            let copied = io::copy(&mut file.take(entry.size), &mut writer)?;
+            io::copy(&mut io::repeat(0).take(entry.size - copied), &mut writer)?;
+            writer.write_all(tar_padding(entry.size))?;
+        }
+    }
+    writer.write_all(&[0; TAR_BLOCK_SIZE * 2])?;
+    writer.finish()?;
+    Ok(())
+}
+
+/// Answers `GET /dir?archive=zip` with the directory tree, written while it is sent
+pub fn handle(path: &Path, format: Format, options: &StaticOptions) -> Response {
+    let mut entries = Vec::new();
+    if let Err(err) = collect(path, "", options.show_hidden, &mut entries) {
+        return Response::with_text(status::INTERNAL_SERVER_ERROR, &err.to_string());
+    }
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    if total > options.max_archive_size {
+        return Response::with_text(
+            status::PAYLOAD_TOO_LARGE,
+            "the directory is larger than the archive size limit",
+        );
+    }
+    if format == Format::Zip
+        && (entries.len() > ZIP_MAX_ENTRIES || zip_size(&entries) > ZIP_MAX_OFFSET)
+    {
+        return Response::with_text(
+            status::PAYLOAD_TOO_LARGE,
+            "the directory is too large for a zip archive, try ?archive=tar.gz",
+        );
+    }
+
+    let name = match path.file_name() {
+        Some(name) => name.to_string_lossy().into_owned(),
+        None => String::from("archive"),
+    };
+    let filename = format!("{}.{}", name, format.extension());
+    // files can change while they are read, so the length is left to the chunked coding
+    let mut response = Response::new();
+    response.set_header("Content-Type", format.content_type());
+    response.set_header(
+        "Content-Disposition",
+        &format!(
+            "attachment; filename={}; filename*={}",
+            header::quote(&filename),
+            header::encode_ext_value(&filename)
+        ),
+    );
+    response.set_stream(BodyStream::new(move |writer| match format {
+        Format::Zip => write_zip(writer, &entries),
+        Format::TarGz => write_tar_gz(writer, &entries),
+    }));
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::GzDecoder;
+    use std::{fs, io::Read, path::PathBuf, process};
+
+    use super::{collect, write_tar_gz, write_zip, zip_size, Format};
+
+    fn fixture(name: &str) -> PathBuf {
+        let root = std::env::temp_dir().join(format!("archive-{}-{}", name, process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("docs/nested")).unwrap();
+        fs::write(root.join("docs/a.txt"), "hello").unwrap();
+        fs::write(root.join("docs/nested/b.txt"), "world!").unwrap();
+        fs::write(root.join("docs/.secret"), "hidden").unwrap();
+        root
+    }
+
+    #[test]
+    fn format_from_query() {
+        assert_eq!(Format::from_query("ZIP"), Some(Format::Zip));
+        assert_eq!(Format::from_query("tgz"), Some(Format::TarGz));
+        assert_eq!(Format::from_query("rar"), None);
+    }
+
+    #[test]
+    fn collect_skips_hidden() {
+        let root = fixture("collect");
+        let mut entries = Vec::new();
+        collect(&root, "", false, &mut entries).unwrap();
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["docs/", "docs/a.txt", "docs/nested/", "docs/nested/b.txt"]
+        );
+
+        let mut entries = Vec::new();
+        collect(&root, "", true, &mut entries).unwrap();
+        assert_eq!(entries[1].name, "docs/.secret");
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn zip_layout() {
+        let root = fixture("zip");
+        let mut entries = Vec::new();
+        collect(&root, "", false, &mut entries).unwrap();
+        let mut zip = Vec::new();
+        write_zip(&mut zip, &entries).unwrap();
+        assert_eq!(zip.len() as u64, zip_size(&entries));
+        assert_eq!(&zip[..4], b"PK\x03\x04");
+        // end of central directory: 4 entries, then the central directory size and offset
+        let end = &zip[zip.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]), 4);
+        let offset = u32::from_le_bytes([end[16], end[17], end[18], end[19]]) as usize;
+        assert_eq!(&zip[offset..offset + 4], b"PK\x01\x02");
+        // crc32 of "hello"
+        let local = zip
+            .windows(10)
+            .position(|window| window == b"docs/a.txt")
+            .unwrap();
+        let descriptor = local + "docs/a.txt".len() + 5;
+        assert_eq!(
+            &zip[descriptor..descriptor + 8],
+            b"PK\x07\x08\x86\xa6\x10\x36"
+        );
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn tar_gz_layout() {
+        let root = fixture("tar");
+        let long_name = "x".repeat(120);
+        fs::write(root.join("docs").join(&long_name), "long").unwrap();
+        let mut entries = Vec::new();
+        collect(&root.join("docs"), "", false, &mut entries).unwrap();
+        let mut compressed = Vec::new();
+        write_tar_gz(&mut compressed, &entries).unwrap();
+        let mut tar = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut tar)
+            .unwrap();
+
+        assert_eq!(tar.len() % 512, 0);
+        assert_eq!(&tar[..5], b"a.txt");
+        assert_eq!(&tar[124..136], b"00000000005\0");
+        assert_eq!(&tar[257..265], b"ustar  \0");
+        assert_eq!(&tar[512..517], b"hello");
+        let checksum: u32 = tar[..512]
+            .iter()
+            .enumerate()
+            .map(|(index, byte)| {
+                if (148..156).contains(&index) {
+                    32
+                } else {
+                    *byte as u32
+                }
+            })
+            .sum();
+        assert_eq!(&tar[148..154], format!("{:06o}", checksum).as_bytes());
+        assert_eq!(&tar[1024..1031], b"nested/");
+        assert_eq!(tar[1024 + 156], b'5');
+        // the long name comes first as its own entry
+        assert_eq!(&tar[2560..2573], b"././@LongLink");
+        assert_eq!(tar[2560 + 156], b'L');
+        assert_eq!(&tar[3072..3192], long_name.as_bytes());
+        assert_eq!(&tar[3584..3684], &long_name.as_bytes()[..100]);
+        assert!(tar[tar.len() - 1024..].iter().all(|byte| *byte == 0));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
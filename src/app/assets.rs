@@ -0,0 +1,136 @@
+use std::{fs, path::Path};
+
+use super::middleware::{push_segments, serve_file, StaticOptions};
+use crate::infra::http::{
+    message::{HttpMessage, Request, Response},
+    mime, status,
+    url::Url,
+};
+
+pub const ASSETS_PREFIX: &[u8] = b".assets";
+
+const BUILTIN: [(&str, &[u8]); 5] = [
+    ("style.css", include_bytes!("assets/style.css")),
+    ("icons/file.png", include_bytes!("assets/icons/file.png")),
+    ("icons/dir.png", include_bytes!("assets/icons/dir.png")),
+    (
+        "icons/symlink.png",
+        include_bytes!("assets/icons/symlink.png"),
+    ),
+    (
+        "icons/unknown.png",
+        include_bytes!("assets/icons/unknown.png"),
+    ),
+];
+
+/// CSS, scripts and icons for the templates live below `/.assets/`, apart from the served root
+pub fn is_asset(url: &Url) -> bool {
+    url.segments.first().map(|segment| segment.as_slice()) == Some(ASSETS_PREFIX)
+}
+
+/// Serves a file of the assets directory, or the built-in asset of the same name
+pub fn handle(request: &Request, options: &StaticOptions) -> Response {
+    if request.method != "GET" {
+        return Response::with_text(status::METHOD_NOT_ALLOWED, "method not allowed");
+    }
+    let segments: Vec<&[u8]> = request.url.segments[1..]
+        .iter()
+        .map(|segment| segment.as_slice())
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    if let Some(dir) = &options.assets_dir {
+        if let Some(path) = push_segments(dir.clone(), segments.iter().copied()) {
+            if let Ok(info) = fs::metadata(&path) {
+                if info.is_file() {
+                    return serve_file(request, &path, &info);
+                }
+            }
+        }
+    }
+    let name = segments.join(&b'/');
+    match BUILTIN
+        .iter()
+        .find(|(builtin, _)| builtin.as_bytes() == name)
+    {
+        Some((name, body)) => {
+            let extension = Path::new(name)
+                .extension()
+                .and_then(|extension| extension.to_str())
+                .unwrap_or("");
+            let mut response = Response::new();
+            response.set_header(
+                "Content-Type",
+                mime::content_type(extension, body)
+                    .as_deref()
+                    .unwrap_or("application/octet-stream"),
+            );
+            response.set_body(body);
+            response
+        }
+        None => Response::with_text(status::NOT_FOUND, "not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::{handle, is_asset};
+    use crate::app::middleware::StaticOptions;
+    use crate::infra::http::{
+        message::{HttpMessage, Request, Response},
+        status,
+        url::Url,
+    };
+
+    fn get(target: &str, options: &StaticOptions) -> Response {
+        let request = Request {
+            method: String::from("GET"),
+            path: String::from(target),
+            url: Url::parse(target.as_bytes()).unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers: Default::default(),
+            body: Vec::new(),
+            body_reader: None,
+        };
+        assert!(is_asset(&request.url));
+        handle(&request, options)
+    }
+
+    #[test]
+    fn builtin_assets() {
+        let options = StaticOptions::new();
+        let response = get("/.assets/icons/dir.png", &options);
+        assert_eq!(response.code, status::OK);
+        assert_eq!(response.get_header("Content-Type"), Some("image/png"));
+        assert_eq!(response.body, include_bytes!("assets/icons/dir.png"));
+        assert_eq!(
+            get("/.assets/missing.css", &options).code,
+            status::NOT_FOUND
+        );
+        assert!(!is_asset(&Url::parse(b"/docs/.assets/style.css").unwrap()));
+    }
+
+    #[test]
+    fn override_assets() {
+        let dir = std::env::temp_dir().join(format!("https-server-app-assets-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("style.css"), "body { color: red; }").unwrap();
+        fs::write(dir.join("app.js"), "console.log(1)").unwrap();
+        let mut options = StaticOptions::new();
+        options.assets_dir = Some(dir.clone());
+
+        let response = get("/.assets/style.css", &options);
+        assert_eq!(response.body, b"body { color: red; }");
+        assert!(response
+            .get_header("Content-Type")
+            .unwrap()
+            .starts_with("text/css"));
+        assert_eq!(get("/.assets/app.js", &options).body, b"console.log(1)");
+        // anything not overridden still comes from the built-ins
+        let response = get("/.assets/icons/file.png", &options);
+        assert_eq!(response.body, include_bytes!("assets/icons/file.png"));
+        assert_eq!(get("/.assets/../secret", &options).code, status::NOT_FOUND);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,323 @@
+use flate2::{
+    write::{GzEncoder, ZlibEncoder},
+    Compression,
+};
+use std::{
+    fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use super::middleware::add_vary;
+use crate::infra::http::{
+    header,
+    message::{HttpMessage, Request, Response},
+    status,
+};
+
+/// brotli quality, the highest levels are too slow to run on every request
+const BROTLI_QUALITY: u32 = 5;
+const BROTLI_WINDOW: u32 = 22;
+
+/// larger bodies are sent as they are, compressing them on every request costs too much time
+const MAX_COMPRESS_SIZE: usize = 4 * 1024 * 1024;
+
+/// Content codings of a response body, see RFC 9110 section 8.4.1
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Brotli,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    /// in the order preferred when the client weighs them equally
+    pub const ALL: [Encoding; 3] = [Encoding::Brotli, Encoding::Gzip, Encoding::Deflate];
+
+    pub fn token(&self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+    /// suffix of a precompressed sibling, like `app.js.br` next to `app.js`
+    fn extension(&self) -> Option<&'static str> {
+        match self {
+            Encoding::Brotli => Some("br"),
+            Encoding::Gzip => Some("gz"),
+            Encoding::Deflate => None,
+        }
+    }
+    /// `deflate` is the zlib format, not raw deflate, see RFC 9110 section 8.4.1.2
+    pub fn encode(&self, body: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Encoding::Brotli => {
+                let mut encoder =
+                    brotli::CompressorWriter::new(Vec::new(), 4096, BROTLI_QUALITY, BROTLI_WINDOW);
+                encoder.write_all(body)?;
+                Ok(encoder.into_inner())
+            }
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(body)?;
+                encoder.finish()
+            }
+        }
+    }
+}
+
+/// Picks the most preferred of `available` that an `Accept-Encoding` header allows,
+/// see RFC 9110 section 12.5.3. `None` leaves the body as it is.
+pub fn negotiate(
+    accept: Option<&str>,
+    available: impl IntoIterator<Item = Encoding>,
+) -> Option<Encoding> {
+    let codings: Vec<(String, f32)> = accept?
+        .split(',')
+        .map(header::parse_params)
+        .map(|(coding, params)| {
+            let quality = header::get_param(&params, "q")
+                .and_then(|q| q.parse().ok())
+                .unwrap_or(1.0);
+            (coding.to_ascii_lowercase(), quality)
+        })
+        .collect();
+    let quality = |encoding: Encoding| {
+        let find = |token: &str| codings.iter().find(|(coding, _)| coding == token);
+        let found = match encoding {
+            Encoding::Gzip => find("gzip").or_else(|| find("x-gzip")),
+            encoding => find(encoding.token()),
+        };
+        found
+            .or_else(|| find("*"))
+            .map(|(_, quality)| *quality)
+            .unwrap_or(0.0)
+    };
+    let mut best: Option<(Encoding, f32)> = None;
+    for encoding in available {
+        let quality = quality(encoding);
+        if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+            best = Some((encoding, quality));
+        }
+    }
+    best.map(|(encoding, _)| encoding)
+}
+
+/// Text-like types shrink well, images, media and archives are compressed already
+pub fn is_compressible(content_type: &str) -> bool {
+    let media_type = header::parse_params(content_type).0.to_ascii_lowercase();
+    media_type.starts_with("text/")
+        || media_type.ends_with("+xml")
+        || media_type.ends_with("+json")
+        || matches!(
+            media_type.as_str(),
+            "application/json"
+                | "application/ld+json"
+                | "application/javascript"
+                | "application/xml"
+                | "application/wasm"
+                | "application/rtf"
+                | "application/x-csh"
+                | "application/x-sh"
+                | "application/vnd.ms-fontobject"
+                | "font/otf"
+                | "font/ttf"
+        )
+}
+
+/// The precompressed siblings of a file, `app.js.br` and `app.js.gz` next to `app.js`.
+/// A sibling older than the file is left out, it would serve stale content.
+pub fn siblings(path: &Path, info: &fs::Metadata) -> Vec<(Encoding, PathBuf, fs::Metadata)> {
+    let name = match path.file_name() {
+        Some(name) => name,
+        None => return Vec::new(),
+    };
+    Encoding::ALL
+        .iter()
+        .filter_map(|encoding| {
+            let mut sibling_name = name.to_os_string();
+            sibling_name.push(".");
+            sibling_name.push(encoding.extension()?);
+            let sibling = path.with_file_name(sibling_name);
+            let sibling_info = fs::metadata(&sibling).ok()?;
+            let stale = match (info.modified(), sibling_info.modified()) {
+                (Ok(modified), Ok(sibling_modified)) => sibling_modified < modified,
+                _ => false,
+            };
+            if !sibling_info.is_file() || stale {
+                return None;
+            }
+            Some((*encoding, sibling, sibling_info))
+        })
+        .collect()
+}
+
+/// The entity tag of an encoded representation, `"1a-2f"` becomes `"1a-2f-br"`
+pub fn encoded_etag(etag: &str, encoding: Encoding) -> String {
+    match etag.strip_suffix('"') {
+        Some(tag) => format!("{}-{}\"", tag, encoding.token()),
+        None => String::from(etag),
+    }
+}
+
+/// Compresses a buffered body of a compressible type from `min_size` up to `MAX_COMPRESS_SIZE` bytes
+pub fn compress_response(request: &Request, response: &mut Response, min_size: usize) {
+    if response.stream.is_some()
+        || response.get_header("Content-Encoding").is_some()
+        || matches!(
+            response.code,
+            status::NO_CONTENT | status::PARTIAL_CONTENT | status::NOT_MODIFIED
+        )
+    {
+        return;
+    }
+    let compressible = response
+        .get_header("Content-Type")
+        .is_some_and(is_compressible);
+    if !compressible || response.body.len() < min_size || response.body.len() > MAX_COMPRESS_SIZE {
+        return;
+    }
+    add_vary(response, "Accept-Encoding");
+    let encoding = match negotiate(request.get_header("Accept-Encoding"), Encoding::ALL) {
+        Some(encoding) => encoding,
+        None => return,
+    };
+    let body = match encoding.encode(&response.body) {
+        Ok(body) if body.len() < response.body.len() => body,
+        _ => return,
+    };
+    response.set_body(&body);
+    response.set_header("Content-Encoding", encoding.token());
+    if let Some(etag) = response.get_header("ETag").map(String::from) {
+        response.set_header("ETag", &encoded_etag(&etag, encoding));
+    }
+    // ranges address the bytes of the file, a body compressed on the fly is only sent whole
+    response.headers.remove("Accept-Ranges");
+}
+
+#[cfg(test)]
+mod tests {
+    use flate2::read::{GzDecoder, ZlibDecoder};
+    use std::{fs, io::Read, process};
+
+    use super::{compress_response, negotiate, siblings, Encoding, MAX_COMPRESS_SIZE};
+    use crate::infra::http::{
+        message::{HttpMessage, Request, Response},
+        status,
+        url::Url,
+    };
+
+    #[test]
+    fn negotiate_encoding() {
+        let all = Encoding::ALL;
+        assert_eq!(negotiate(None, all), None);
+        assert_eq!(
+            negotiate(Some("gzip, deflate, br"), all),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(negotiate(Some("br;q=0.5, gzip"), all), Some(Encoding::Gzip));
+        assert_eq!(negotiate(Some("x-gzip"), all), Some(Encoding::Gzip));
+        assert_eq!(
+            negotiate(Some("*;q=0.1, br;q=0"), all),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(negotiate(Some("identity"), all), None);
+        assert_eq!(negotiate(Some("gzip"), [Encoding::Brotli]), None);
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let body = "hello world ".repeat(100);
+        let mut decoded = String::new();
+        let encoded = Encoding::Gzip.encode(body.as_bytes()).unwrap();
+        GzDecoder::new(encoded.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let mut decoded = String::new();
+        let encoded = Encoding::Deflate.encode(body.as_bytes()).unwrap();
+        ZlibDecoder::new(encoded.as_slice())
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+
+        let mut decoded = String::new();
+        let encoded = Encoding::Brotli.encode(body.as_bytes()).unwrap();
+        brotli::Decompressor::new(encoded.as_slice(), 4096)
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, body);
+    }
+
+    #[test]
+    fn compress_responses() {
+        let mut request = Request {
+            method: String::from("GET"),
+            path: String::from("/"),
+            url: Url::parse(b"/").unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers: Default::default(),
+            body: Vec::new(),
+            body_reader: None,
+        };
+        request.set_header("Accept-Encoding", "gzip");
+        let text = "<p>hello</p>".repeat(200);
+
+        let mut response = Response::with_text(status::OK, &text);
+        response.set_header("Content-Type", "text/html; charset=utf-8");
+        response.set_header("ETag", "\"1a-2f\"");
+        response.set_header("Accept-Ranges", "bytes");
+        response.set_header("Vary", "Accept");
+        compress_response(&request, &mut response, 1024);
+        assert_eq!(response.get_header("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.get_header("ETag"), Some("\"1a-2f-gzip\""));
+        assert_eq!(response.get_header("Vary"), Some("Accept, Accept-Encoding"));
+        assert_eq!(response.get_header("Accept-Ranges"), None);
+        assert!(response.body.len() < text.len());
+
+        // too small to be worth it, but it would be compressed once larger
+        let mut response = Response::with_text(status::OK, "<p>hello</p>");
+        response.set_header("Content-Type", "text/html");
+        compress_response(&request, &mut response, 1024);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let mut response =
+            Response::with_text(status::OK, &"<p>hello</p>".repeat(MAX_COMPRESS_SIZE / 10));
+        response.set_header("Content-Type", "text/html");
+        compress_response(&request, &mut response, 1024);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+
+        let mut response = Response::with_text(status::OK, &text);
+        response.set_header("Content-Type", "image/png");
+        compress_response(&request, &mut response, 1024);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+        assert_eq!(response.get_header("Vary"), None);
+
+        let mut response = Response::with_text(status::PARTIAL_CONTENT, &text);
+        response.set_header("Content-Type", "text/html");
+        compress_response(&request, &mut response, 1024);
+        assert_eq!(response.get_header("Content-Encoding"), None);
+    }
+
+    #[test]
+    fn precompressed_siblings() {
+        let dir = std::env::temp_dir().join(format!("https-server-app-siblings-{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("app.js");
+        fs::write(&path, "console.log(1)").unwrap();
+        fs::write(dir.join("app.js.gz"), "gzip").unwrap();
+        fs::write(dir.join("app.js.zst"), "zstd").unwrap();
+        let found = siblings(&path, &fs::metadata(&path).unwrap());
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].0, Encoding::Gzip);
+        assert_eq!(found[0].1, dir.join("app.js.gz"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
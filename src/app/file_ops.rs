@@ -0,0 +1,481 @@
+use serde_json::json;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    process,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+use super::middleware::{create_temp_file, is_reserved, push_segments};
+use crate::infra::http::{
+    body::Body,
+    message::{HttpMessage, Request, Response},
+    status::{self, Status},
+    url,
+};
+
+/// the operations only take a few short fields
+const MAX_BODY_SIZE: u64 = 64 * 1024;
+
+fn json_response(code: Status, value: serde_json::Value) -> Response {
+    let mut response = Response::new();
+    response.set_code(code);
+    response.set_header("Content-Type", "application/json");
+    response.set_body(&Vec::from(value.to_string()));
+    response
+}
+
+fn error(code: Status, message: &str) -> Response {
+    json_response(code, json!({ "ok": false, "error": message }))
+}
+
+fn io_error(err: io::Error) -> Response {
+    match err.kind() {
+        io::ErrorKind::NotFound => error(status::NOT_FOUND, "not found"),
+        io::ErrorKind::AlreadyExists => error(status::CONFLICT, "already exists"),
+        io::ErrorKind::PermissionDenied => error(status::FORBIDDEN, "permission denied"),
+        _ => error(status::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+/// The operation a request asks for, given as `POST /path?action=mkdir`
+pub fn action(request: &Request) -> Option<String> {
+    if request.method != "POST" {
+        return None;
+    }
+    request.url.query.get("action").map(String::from)
+}
+
+/// Copies a file through a temp file, or a directory tree entry by entry
+fn copy_all(from: &Path, to: &Path) -> io::Result<()> {
+    let file_type = fs::symlink_metadata(from)?.file_type();
+    if file_type.is_dir() {
+        fs::create_dir(to)?;
+        for entry in fs::read_dir(from)? {
+            let entry = entry?;
+            copy_all(&entry.path(), &to.join(entry.file_name()))?;
+        }
+        Ok(())
+    } else if file_type.is_file() {
+        let (temp_path, mut file) = create_temp_file(to)?;
+        let copied = fs::File::open(from)
+            .and_then(|mut source| io::copy(&mut source, &mut file))
+            .and_then(|_| fs::rename(&temp_path, to));
+        if copied.is_err() {
+            fs::remove_file(&temp_path).ok();
+        }
+        copied
+    } else {
+        // links could point anywhere, copying them would escape the root
+        Ok(())
+    }
+}
+
+fn remove_all(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// An unused hidden name next to `path`, for staging what replaces it
+fn temp_sibling(path: &Path) -> PathBuf {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    loop {
+        let sibling = path.with_file_name(format!(
+            ".{}.replace-{}-{}",
+            name,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        if fs::symlink_metadata(&sibling).is_err() {
+            return sibling;
+        }
+    }
+}
+
+/// Whether both paths name the same entry, like `a.txt` and `A.txt` on a case-insensitive filesystem
+#[cfg(unix)]
+fn same_entry(a: &Path, b: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (fs::symlink_metadata(a), fs::symlink_metadata(b)) {
+        (Ok(a), Ok(b)) => a.dev() == b.dev() && a.ino() == b.ino(),
+        _ => false,
+    }
+}
+
+#[cfg(not(unix))]
+fn same_entry(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// Renames `from` onto `to`, replacing whatever is there. A file takes the place of a file
+/// in one step, a directory is set aside first and only removed once `from` is in place.
+fn replace(from: &Path, to: &Path) -> io::Result<()> {
+    let replaced = match fs::symlink_metadata(to) {
+        Ok(replaced) => replaced,
+        Err(_) => return fs::rename(from, to),
+    };
+    if !replaced.is_dir() && !fs::symlink_metadata(from)?.is_dir() {
+        return fs::rename(from, to);
+    }
+    let aside = temp_sibling(to);
+    fs::rename(to, &aside)?;
+    match fs::rename(from, to) {
+        Ok(_) => {
+            remove_all(&aside).ok();
+            Ok(())
+        }
+        Err(err) => {
+            fs::rename(&aside, to).ok();
+            Err(err)
+        }
+    }
+}
+
+/// Handles mkdir, rename, move and copy below `root`, answering with JSON
+///
+/// Example
+/// ```ignore
+/// POST /docs/new?action=mkdir
+/// POST /docs/a.txt?action=rename   name=b.txt
+/// POST /docs/a.txt?action=move     to=/archive/a.txt&overwrite=1
+/// POST /docs?action=copy           to=/docs-backup
+/// ```
+pub fn handle(request: &mut Request, root: &str, action: &str) -> Response {
+    let body = match Body::from_request(request, MAX_BODY_SIZE) {
+        Ok(body) => body,
+        Err(err) => return error(err.code, &err.error.to_string()),
+    };
+    let field = |name: &str| -> Option<String> {
+        match &body {
+            Body::Form(form) => form.get(name).map(String::from),
+            Body::Json(value) => match &value[name] {
+                serde_json::Value::String(value) => Some(value.clone()),
+                serde_json::Value::Bool(value) => Some(value.to_string()),
+                _ => None,
+            },
+            _ => None,
+        }
+    };
+    let flag = |name: &str| matches!(field(name).as_deref(), Some("1" | "true" | "on"));
+
+    let segments: Vec<Vec<u8>> = request
+        .url
+        .segments
+        .iter()
+        .filter(|segment| !segment.is_empty())
+        .cloned()
+        .collect();
+    let path = match push_segments(
+        PathBuf::from(root),
+        segments.iter().map(|segment| segment.as_slice()),
+    ) {
+        Some(path) => path,
+        None => return error(status::FORBIDDEN, "forbidden"),
+    };
+
+    if is_reserved(Path::new(root), &path) {
+        return error(status::FORBIDDEN, "reserved name");
+    }
+    if action == "mkdir" {
+        if path.exists() {
+            return error(status::CONFLICT, "already exists");
+        }
+        let created = if flag("parents") {
+            fs::create_dir_all(&path)
+        } else {
+            fs::create_dir(&path)
+        };
+        return match created {
+            Ok(_) => json_response(
+                status::CREATED,
+                json!({ "ok": true, "path": url::encode_segments(&segments) }),
+            ),
+            Err(err) => io_error(err),
+        };
+    }
+
+    if segments.is_empty() {
+        return error(
+            status::FORBIDDEN,
+            "the root cannot be renamed, moved or copied",
+        );
+    }
+    if fs::symlink_metadata(&path).is_err() {
+        return error(status::NOT_FOUND, "not found");
+    }
+    let target_segments: Vec<Vec<u8>> = match action {
+        "rename" => {
+            let name = match field("name") {
+                Some(name) => name,
+                None => return error(status::BAD_REQUEST, "missing name"),
+            };
+            if name.is_empty() || name == "." || name == ".." || name.contains(['/', '\\', '\0']) {
+                return error(status::BAD_REQUEST, "invalid name");
+            }
+            let mut target = segments[..segments.len() - 1].to_vec();
+            target.push(Vec::from(name));
+            target
+        }
+        "move" | "copy" => match field("to") {
+            Some(to) => to
+                .split('/')
+                .filter(|segment| !segment.is_empty())
+                .map(Vec::from)
+                .collect(),
+            None => return error(status::BAD_REQUEST, "missing destination"),
+        },
+        _ => return error(status::BAD_REQUEST, "unknown action"),
+    };
+    let target = match push_segments(
+        PathBuf::from(root),
+        target_segments.iter().map(|segment| segment.as_slice()),
+    ) {
+        Some(target) if !target_segments.is_empty() => target,
+        _ => return error(status::FORBIDDEN, "forbidden"),
+    };
+    if is_reserved(Path::new(root), &target) {
+        return error(status::FORBIDDEN, "reserved name");
+    }
+
+    if target == path {
+        return error(status::CONFLICT, "source and destination are the same");
+    }
+    if target.starts_with(&path) {
+        return error(status::CONFLICT, "cannot place a directory inside itself");
+    }
+    if path.starts_with(&target) {
+        return error(
+            status::CONFLICT,
+            "cannot replace a directory containing the source",
+        );
+    }
+    match target.parent() {
+        Some(parent) if parent.is_dir() => {}
+        _ => return error(status::NOT_FOUND, "destination directory not found"),
+    }
+    let exists = fs::symlink_metadata(&target).is_ok();
+    if exists && !flag("overwrite") {
+        return error(status::CONFLICT, "destination already exists");
+    }
+    // only the case of the name differs, replacing the target would remove the source
+    let same = exists && same_entry(&path, &target);
+    if same && action == "copy" {
+        return error(status::CONFLICT, "source and destination are the same");
+    }
+
+    // the destination is only replaced once the new entry is complete, a failure keeps it
+    let done = if action == "copy" {
+        let staged = if exists {
+            temp_sibling(&target)
+        } else {
+            target.clone()
+        };
+        let copied = copy_all(&path, &staged).and_then(|_| match exists {
+            true => replace(&staged, &target),
+            false => Ok(()),
+        });
+        if copied.is_err() {
+            remove_all(&staged).ok();
+        }
+        copied
+    } else if same {
+        fs::rename(&path, &target)
+    } else {
+        replace(&path, &target)
+    };
+    match done {
+        Ok(_) => json_response(
+            if action == "copy" {
+                status::CREATED
+            } else {
+                status::OK
+            },
+            json!({ "ok": true, "path": url::encode_segments(&target_segments) }),
+        ),
+        Err(err) => io_error(err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, path::PathBuf, process};
+
+    use super::{action, handle};
+    use crate::infra::http::{
+        message::{HttpMessage, Request, Response},
+        status,
+        url::Url,
+    };
+
+    fn setup(name: &str) -> (PathBuf, String) {
+        let dir =
+            std::env::temp_dir().join(format!("https-server-app-ops-{}-{}", name, process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        (dir, root.to_string_lossy().into_owned())
+    }
+
+    fn post(root: &str, target: &str, form: &str) -> Response {
+        let mut request = Request {
+            method: String::from("POST"),
+            path: String::from(target),
+            url: Url::parse(target.as_bytes()).unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers: Default::default(),
+            body: Vec::from(form),
+            body_reader: None,
+        };
+        if !form.is_empty() {
+            request.set_header("Content-Type", "application/x-www-form-urlencoded");
+        }
+        let action = action(&request).unwrap();
+        handle(&mut request, root, &action)
+    }
+
+    #[test]
+    fn make_directories() {
+        let (dir, root) = setup("mkdir");
+        let root_path = PathBuf::from(&root);
+        assert_eq!(post(&root, "/new?action=mkdir", "").code, status::CREATED);
+        assert!(root_path.join("new").is_dir());
+        assert_eq!(post(&root, "/new?action=mkdir", "").code, status::CONFLICT);
+        assert_eq!(
+            post(&root, "/a/b/c?action=mkdir", "").code,
+            status::NOT_FOUND
+        );
+        assert_eq!(
+            post(&root, "/a/b/c?action=mkdir", "parents=1").code,
+            status::CREATED
+        );
+        assert!(root_path.join("a/b/c").is_dir());
+        // the urls of resumable uploads belong to the server
+        assert_eq!(
+            post(&root, "/.tus?action=mkdir", "").code,
+            status::FORBIDDEN
+        );
+        assert!(!root_path.join(".tus").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rename_move_and_copy() {
+        let (dir, root) = setup("move");
+        let root_path = PathBuf::from(&root);
+        fs::create_dir_all(root_path.join("docs/sub")).unwrap();
+        fs::create_dir(root_path.join("archive")).unwrap();
+        fs::write(root_path.join("docs/a.txt"), "a").unwrap();
+        fs::write(root_path.join("docs/sub/b.txt"), "b").unwrap();
+
+        let response = post(&root, "/docs/a.txt?action=rename", "name=c.txt");
+        assert_eq!(response.code, status::OK);
+        assert_eq!(fs::read(root_path.join("docs/c.txt")).unwrap(), b"a");
+        assert!(!root_path.join("docs/a.txt").exists());
+
+        let response = post(&root, "/docs/c.txt?action=move", "to=/archive/c.txt");
+        assert_eq!(response.code, status::OK);
+        assert!(root_path.join("archive/c.txt").is_file());
+        assert!(!root_path.join("docs/c.txt").exists());
+
+        let response = post(&root, "/archive/c.txt?action=copy", "to=/docs/c.txt");
+        assert_eq!(response.code, status::CREATED);
+        assert_eq!(fs::read(root_path.join("docs/c.txt")).unwrap(), b"a");
+        assert!(root_path.join("archive/c.txt").is_file());
+
+        let response = post(&root, "/docs?action=copy", "to=/backup");
+        assert_eq!(response.code, status::CREATED);
+        assert_eq!(fs::read(root_path.join("backup/sub/b.txt")).unwrap(), b"b");
+        assert_eq!(fs::read(root_path.join("backup/c.txt")).unwrap(), b"a");
+        assert!(root_path.join("docs/sub/b.txt").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overwrite_destinations() {
+        let (dir, root) = setup("overwrite");
+        let root_path = PathBuf::from(&root);
+        fs::create_dir_all(root_path.join("old")).unwrap();
+        fs::create_dir_all(root_path.join("new")).unwrap();
+        fs::write(root_path.join("old/stale.txt"), "stale").unwrap();
+        fs::write(root_path.join("new/fresh.txt"), "fresh").unwrap();
+        fs::write(root_path.join("a.txt"), "a").unwrap();
+        fs::write(root_path.join("b.txt"), "b").unwrap();
+
+        let response = post(&root, "/a.txt?action=copy", "to=/b.txt");
+        assert_eq!(response.code, status::CONFLICT);
+        assert_eq!(fs::read(root_path.join("b.txt")).unwrap(), b"b");
+        let response = post(&root, "/a.txt?action=copy", "to=/b.txt&overwrite=1");
+        assert_eq!(response.code, status::CREATED);
+        assert_eq!(fs::read(root_path.join("b.txt")).unwrap(), b"a");
+
+        let response = post(&root, "/new?action=copy", "to=/old&overwrite=1");
+        assert_eq!(response.code, status::CREATED);
+        assert!(root_path.join("old/fresh.txt").is_file());
+        assert!(!root_path.join("old/stale.txt").exists());
+
+        let response = post(&root, "/b.txt?action=move", "to=/a.txt&overwrite=1");
+        assert_eq!(response.code, status::OK);
+        assert_eq!(fs::read(root_path.join("a.txt")).unwrap(), b"a");
+        assert!(!root_path.join("b.txt").exists());
+
+        let response = post(&root, "/new?action=rename", "name=old&overwrite=1");
+        assert_eq!(response.code, status::OK);
+        assert!(root_path.join("old/fresh.txt").is_file());
+        assert!(!root_path.join("new").exists());
+
+        // nothing is left behind next to the destinations
+        let mut names: Vec<String> = fs::read_dir(&root_path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["a.txt", "old"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn refuse_escapes() {
+        let (dir, root) = setup("escape");
+        let root_path = PathBuf::from(&root);
+        fs::write(root_path.join("a.txt"), "a").unwrap();
+
+        assert_eq!(
+            post(&root, "/?action=rename", "name=x").code,
+            status::FORBIDDEN
+        );
+        assert_eq!(
+            post(&root, "/?action=copy", "to=/x").code,
+            status::FORBIDDEN
+        );
+        let response = post(&root, "/a.txt?action=move", "to=../outside.txt");
+        assert_eq!(response.code, status::FORBIDDEN);
+        let response = post(&root, "/a.txt?action=copy", "to=/docs/../../outside.txt");
+        assert_eq!(response.code, status::FORBIDDEN);
+        let response = post(&root, "/a.txt?action=move", "to=/");
+        assert_eq!(response.code, status::FORBIDDEN);
+        let response = post(&root, "/a.txt?action=rename", "name=..");
+        assert_eq!(response.code, status::BAD_REQUEST);
+        let response = post(&root, "/a.txt?action=rename", "name=../b.txt");
+        assert_eq!(response.code, status::BAD_REQUEST);
+        let response = post(&root, "/%2e%2e/outside?action=mkdir", "");
+        assert_eq!(response.code, status::FORBIDDEN);
+        let response = post(&root, "/a.txt?action=move", "to=/.tus");
+        assert_eq!(response.code, status::FORBIDDEN);
+        let response = post(&root, "/a.txt?action=copy", "to=/.assets/a.txt");
+        assert_eq!(response.code, status::FORBIDDEN);
+
+        assert!(root_path.join("a.txt").is_file());
+        assert!(!dir.join("outside.txt").exists());
+        assert!(!dir.join("outside").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
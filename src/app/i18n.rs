@@ -0,0 +1,229 @@
+use serde_json::Value;
+use std::{collections::HashMap, sync::OnceLock};
+
+use crate::infra::http::{
+    header,
+    message::{HttpMessage, Request, Response},
+};
+
+/// Messages are written in English, the catalogs map them to their translation
+const ZH_CN: &str = include_str!("locales/zh-CN.json");
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Locale {
+    En,
+    ZhCn,
+}
+
+impl Locale {
+    #[cfg(feature = "gui")]
+    pub const ALL: [Locale; 2] = [Locale::En, Locale::ZhCn];
+
+    /// Matches a language tag like `zh-CN` or a POSIX locale like `zh_CN.UTF-8`
+    pub fn from_tag(tag: &str) -> Option<Locale> {
+        let tag = tag.split('.').next().unwrap_or("");
+        let tag = tag.trim().replace('_', "-").to_ascii_lowercase();
+        let mut subtags = tag.split('-');
+        match subtags.next() {
+            Some("en") => Some(Locale::En),
+            // traditional Chinese readers are better served by English than by simplified characters
+            Some("zh") if !subtags.any(|subtag| matches!(subtag, "hant" | "tw" | "hk" | "mo")) => {
+                Some(Locale::ZhCn)
+            }
+            _ => None,
+        }
+    }
+    /// Picks the most preferred supported language of an `Accept-Language` header, see RFC 9110 section 12.5.4
+    pub fn from_accept_language(value: &str) -> Locale {
+        let mut ranges: Vec<(&str, f32)> = value
+            .split(',')
+            .map(|item| {
+                let mut params = item.split(';');
+                let range = params.next().unwrap_or("").trim();
+                let quality = params
+                    .filter_map(|param| param.trim().strip_prefix("q="))
+                    .find_map(|q| q.parse().ok())
+                    .unwrap_or(1.0);
+                (range, quality)
+            })
+            .filter(|(_, quality)| *quality > 0.0)
+            .collect();
+        // stable, so equal weights keep the order of the header
+        ranges.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranges
+            .iter()
+            .find_map(|(range, _)| Locale::from_tag(range))
+            .unwrap_or(Locale::En)
+    }
+    pub fn from_request(request: &Request) -> Locale {
+        request
+            .get_header("Accept-Language")
+            .map(Locale::from_accept_language)
+            .unwrap_or(Locale::En)
+    }
+    /// The language of the environment, for the GUI
+    #[cfg(feature = "gui")]
+    pub fn from_env() -> Locale {
+        ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .and_then(|value| Locale::from_tag(&value))
+            .unwrap_or(Locale::En)
+    }
+    pub fn tag(&self) -> &'static str {
+        match self {
+            Locale::En => "en",
+            Locale::ZhCn => "zh-CN",
+        }
+    }
+    /// the name of the language in itself, for choosing it
+    #[cfg(feature = "gui")]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Locale::En => "English",
+            Locale::ZhCn => "简体中文",
+        }
+    }
+    fn catalog(&self) -> Option<&'static HashMap<String, String>> {
+        static ZH_CN_CATALOG: OnceLock<HashMap<String, String>> = OnceLock::new();
+        match self {
+            Locale::En => None,
+            Locale::ZhCn => {
+                Some(ZH_CN_CATALOG.get_or_init(|| serde_json::from_str(ZH_CN).unwrap()))
+            }
+        }
+    }
+    /// Translates an English message, messages missing from the catalog stay in English
+    pub fn text<'a>(&self, message: &'a str) -> &'a str {
+        match self.catalog().and_then(|catalog| catalog.get(message)) {
+            Some(text) => text,
+            None => message,
+        }
+    }
+    /// Translates every `error` field of a JSON value, like the failures of an upload summary
+    fn translate_errors(&self, value: &mut Value) -> bool {
+        match value {
+            Value::Object(map) => {
+                let mut translated = false;
+                for (key, value) in map.iter_mut() {
+                    translated |= match value {
+                        Value::String(error) if key == "error" => {
+                            *error = self.text(error).to_string();
+                            true
+                        }
+                        value => self.translate_errors(value),
+                    };
+                }
+                translated
+            }
+            Value::Array(items) => {
+                let mut translated = false;
+                for item in items {
+                    translated |= self.translate_errors(item);
+                }
+                translated
+            }
+            _ => false,
+        }
+    }
+    /// Translates the message of an error response, plain text or the `error` fields of JSON
+    pub fn translate_response(&self, response: &mut Response) {
+        // files carry an entity tag, their content is never a message of ours
+        if *self == Locale::En || response.get_header("ETag").is_some() {
+            return;
+        }
+        let content_type = response
+            .get_header("Content-Type")
+            .map(|value| header::parse_params(value).0);
+        match content_type.as_deref() {
+            None | Some("text/plain") => {
+                let text = match std::str::from_utf8(&response.body) {
+                    Ok(body) => self.text(body),
+                    Err(_) => return,
+                };
+                if text.as_bytes() != response.body {
+                    response.set_body(&Vec::from(text));
+                }
+            }
+            Some("application/json") => {
+                let mut value: Value = match serde_json::from_slice(&response.body) {
+                    Ok(value) => value,
+                    Err(_) => return,
+                };
+                if self.translate_errors(&mut value) {
+                    response.set_body(&Vec::from(value.to_string()));
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+    use std::collections::HashMap;
+
+    use super::{Locale, ZH_CN};
+    use crate::infra::http::{message::Response, status};
+
+    #[test]
+    fn negotiate_locale() {
+        assert_eq!(Locale::from_accept_language("zh-CN,zh;q=0.9,en;q=0.8"), Locale::ZhCn);
+        assert_eq!(Locale::from_accept_language("en-US,zh-CN;q=0.5"), Locale::En);
+        assert_eq!(Locale::from_accept_language("fr, zh;q=0.3"), Locale::ZhCn);
+        assert_eq!(Locale::from_accept_language("de;q=1, zh-Hans;q=0.9"), Locale::ZhCn);
+        assert_eq!(Locale::from_accept_language("zh-TW, zh;q=0"), Locale::En);
+        assert_eq!(Locale::from_accept_language("*"), Locale::En);
+        assert_eq!(Locale::from_tag("zh_CN.UTF-8"), Some(Locale::ZhCn));
+        assert_eq!(Locale::from_tag("C.UTF-8"), None);
+    }
+
+    #[test]
+    fn translate_messages() {
+        assert_eq!(Locale::ZhCn.text("Overwrite"), "覆盖");
+        assert_eq!(Locale::En.text("Overwrite"), "Overwrite");
+        assert_eq!(Locale::ZhCn.text("no such message"), "no such message");
+
+        let mut response = Response::with_text(status::NOT_FOUND, "directory not found");
+        Locale::ZhCn.translate_response(&mut response);
+        assert_eq!(response.body, Vec::from("目录不存在"));
+
+        let mut response = Response::with_text(status::CONFLICT, r#"{"ok":false,"error":"already exists"}"#);
+        response.headers.insert(String::from("Content-Type"), String::from("application/json"));
+        Locale::ZhCn.translate_response(&mut response);
+        let value: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(value["error"], "已存在");
+
+        let summary = r#"{"ok":false,"saved":["a.txt"],"failed":[{"name":"b.txt","error":"file already exists"}]}"#;
+        let mut response = Response::with_text(status::OK, summary);
+        response.headers.insert(String::from("Content-Type"), String::from("application/json"));
+        Locale::ZhCn.translate_response(&mut response);
+        let value: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(value["failed"][0]["error"], "文件已存在");
+        assert_eq!(value["saved"][0], "a.txt");
+    }
+
+    /// every message the built-in templates ask for has a translation
+    #[test]
+    fn catalog_covers_templates() {
+        let catalog: HashMap<String, String> = serde_json::from_str(ZH_CN).unwrap();
+        let templates = [
+            include_str!("templates/layout.hbs"),
+            include_str!("templates/index.hbs"),
+            include_str!("templates/not_found.hbs"),
+            include_str!("templates/unknown.hbs"),
+            include_str!("templates/preview.hbs"),
+        ];
+        for template in templates {
+            for helper in ["{{t \"", "{{{tjs \"", "(t \""] {
+                for (index, _) in template.match_indices(helper) {
+                    let message = &template[index + helper.len()..];
+                    let message = &message[..message.find('"').unwrap()];
+                    assert!(catalog.contains_key(message), "{:?}", message);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,380 @@
+use serde_json::{json, Value};
+use std::{
+    cmp::Ordering,
+    fs, io,
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use super::{
+    middleware::{etag, is_hidden},
+    thumbnail,
+};
+use crate::infra::http::{
+    date, header,
+    message::{HttpMessage, Request},
+    mime, url,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DirEntryType {
+    File,
+    Dir,
+    Symlink,
+    Unknown,
+}
+
+impl DirEntryType {
+    fn from_file_type(file_type: fs::FileType) -> Self {
+        if file_type.is_file() {
+            DirEntryType::File
+        } else if file_type.is_dir() {
+            DirEntryType::Dir
+        } else if file_type.is_symlink() {
+            DirEntryType::Symlink
+        } else {
+            DirEntryType::Unknown
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            DirEntryType::File => "file",
+            DirEntryType::Dir => "dir",
+            DirEntryType::Symlink => "symlink",
+            DirEntryType::Unknown => "unknown",
+        }
+    }
+}
+
+pub struct Entry {
+    pub name: String,
+    /// percent-encoded path from the root
+    pub href: String,
+    pub file_type: DirEntryType,
+    /// of the link target for links that resolve, otherwise of the entry itself
+    pub metadata: fs::Metadata,
+}
+
+impl Entry {
+    /// whether the entry sorts and behaves like a directory
+    pub fn is_dir(&self) -> bool {
+        self.metadata.is_dir()
+    }
+    pub fn size(&self) -> u64 {
+        if self.is_dir() {
+            0
+        } else {
+            self.metadata.len()
+        }
+    }
+    pub fn modified(&self) -> SystemTime {
+        self.metadata.modified().unwrap_or(UNIX_EPOCH)
+    }
+}
+
+/// Reads the entries of `dir`, whose path from the root is `segments`
+pub fn read(dir: &Path, segments: &[Vec<u8>], show_hidden: bool) -> io::Result<Vec<Entry>> {
+    let mut entries = Vec::new();
+    for child in fs::read_dir(dir)? {
+        let child = child?;
+        let name = child.file_name().to_string_lossy().into_owned();
+        if !show_hidden && is_hidden(&name) {
+            continue;
+        }
+        let file_type = DirEntryType::from_file_type(child.file_type()?);
+        let metadata = match fs::metadata(child.path()) {
+            Ok(metadata) => metadata,
+            Err(_) => child.metadata()?,
+        };
+        let mut segments = segments.to_vec();
+        segments.push(Vec::from(name.as_bytes()));
+        entries.push(Entry {
+            href: url::encode_segments(&segments),
+            name,
+            file_type,
+            metadata,
+        });
+    }
+    Ok(entries)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Name,
+    Size,
+    Modified,
+    Type,
+}
+
+impl SortKey {
+    pub const ALL: [SortKey; 4] = [
+        SortKey::Name,
+        SortKey::Size,
+        SortKey::Modified,
+        SortKey::Type,
+    ];
+
+    pub fn from_query(value: &str) -> Option<SortKey> {
+        SortKey::ALL.iter().copied().find(|key| key.name() == value)
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            SortKey::Name => "name",
+            SortKey::Size => "size",
+            SortKey::Modified => "mtime",
+            SortKey::Type => "type",
+        }
+    }
+}
+
+fn compare_names(a: &str, b: &str) -> Ordering {
+    a.to_lowercase()
+        .cmp(&b.to_lowercase())
+        .then_with(|| a.cmp(b))
+}
+
+/// Sorts by `key` with directories always first, ties are broken by name
+pub fn sort(entries: &mut [Entry], key: SortKey, descending: bool) {
+    entries.sort_by(|a, b| {
+        let ordering = match key {
+            SortKey::Name => Ordering::Equal,
+            SortKey::Size => a.size().cmp(&b.size()),
+            SortKey::Modified => a.modified().cmp(&b.modified()),
+            SortKey::Type => a.file_type.name().cmp(b.file_type.name()),
+        }
+        .then_with(|| compare_names(&a.name, &b.name));
+        let ordering = if descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        b.is_dir().cmp(&a.is_dir()).then(ordering)
+    });
+}
+
+/// Formats `size` with binary units, like `1.5 KiB`
+pub fn format_size(size: u64) -> String {
+    const UNITS: [&str; 6] = ["B", "KiB", "MiB", "GiB", "TiB", "PiB"];
+    let mut value = size as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", size)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Formats `time` as `2006-01-02 15:04` in UTC
+pub fn format_time(time: SystemTime) -> String {
+    let (year, month, day, seconds) = date::to_civil(time);
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60
+    )
+}
+
+/// Permissions in the style of `ls -l`, like `drwxr-xr-x`
+#[cfg(unix)]
+pub fn format_permissions(entry: &Entry) -> String {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = entry.metadata.permissions().mode();
+    let mut permissions = String::from(match entry.file_type {
+        DirEntryType::Dir => "d",
+        DirEntryType::Symlink => "l",
+        DirEntryType::File => "-",
+        DirEntryType::Unknown => "?",
+    });
+    for shift in [6, 3, 0] {
+        let bits = mode >> shift;
+        permissions.push(if bits & 4 != 0 { 'r' } else { '-' });
+        permissions.push(if bits & 2 != 0 { 'w' } else { '-' });
+        permissions.push(if bits & 1 != 0 { 'x' } else { '-' });
+    }
+    permissions
+}
+
+#[cfg(not(unix))]
+pub fn format_permissions(entry: &Entry) -> String {
+    String::from(if entry.metadata.permissions().readonly() {
+        "r-"
+    } else {
+        "rw"
+    })
+}
+
+/// The fields the `index` template shows for an entry
+pub fn to_json(entry: &Entry) -> Value {
+    json!({
+        "name": entry.name,
+        "href": entry.href,
+        "type": entry.file_type.name(),
+        "is_dir": entry.is_dir(),
+        "size": entry.size(),
+        "size_text": if entry.is_dir() { String::from("-") } else { format_size(entry.size()) },
+        "mtime": entry.modified().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+        "mtime_text": format_time(entry.modified()),
+        "permissions": format_permissions(entry),
+        "image": entry.metadata.is_file() && thumbnail::is_image(&entry.name),
+    })
+}
+
+/// An entry of the JSON listing, the schema is pinned down by the `json_schema` test
+pub fn to_api_json(entry: &Entry) -> Value {
+    let is_file = entry.metadata.is_file();
+    let extension = Path::new(&entry.name)
+        .extension()
+        .map(|extension| extension.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    json!({
+        "name": entry.name,
+        "path": entry.href,
+        "type": entry.file_type.name(),
+        "size": if is_file { Some(entry.size()) } else { None },
+        "mtime": entry.modified().duration_since(UNIX_EPOCH).map(|time| time.as_secs()).unwrap_or(0),
+        "mime": if is_file { Some(mime::get_mime(&extension).unwrap_or_else(|| String::from("application/octet-stream"))) } else { None },
+        "etag": if is_file { Some(etag(&entry.metadata)) } else { None },
+    })
+}
+
+/// Whether the client asked for the JSON listing, by `?format=json` or by preferring it in `Accept`
+pub fn wants_json(request: &Request) -> bool {
+    if let Some(format) = request.url.query.get("format") {
+        return format == "json";
+    }
+    let accept = match request.get_header("Accept") {
+        Some(accept) => accept,
+        None => return false,
+    };
+    let quality = |media_type: &str| -> f32 {
+        accept
+            .split(',')
+            .map(header::parse_params)
+            .filter(|(value, _)| value.eq_ignore_ascii_case(media_type))
+            .map(|(_, params)| {
+                header::get_param(&params, "q")
+                    .and_then(|q| q.parse().ok())
+                    .unwrap_or(1.0)
+            })
+            .fold(0.0, f32::max)
+    };
+    let json = quality("application/json");
+    json > 0.0 && json > quality("text/html")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process, time::UNIX_EPOCH};
+
+    use serde_json::Value;
+
+    use super::{format_size, format_time, read, sort, to_api_json, wants_json, SortKey};
+    use crate::infra::http::{message::Request, url::Url};
+
+    #[test]
+    fn sort_directories_first() {
+        let root = std::env::temp_dir().join(format!("listing-sort-{}", process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("b-dir")).unwrap();
+        fs::create_dir_all(root.join("Z-dir")).unwrap();
+        fs::write(root.join("a.txt"), "12345").unwrap();
+        fs::write(root.join("c.txt"), "1").unwrap();
+        fs::write(root.join(".hidden"), "").unwrap();
+
+        let mut entries = read(&root, &[Vec::from("docs")], false).unwrap();
+        sort(&mut entries, SortKey::Name, false);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["b-dir", "Z-dir", "a.txt", "c.txt"]);
+        assert_eq!(entries[2].href, "/docs/a.txt");
+
+        sort(&mut entries, SortKey::Size, true);
+        let names: Vec<&str> = entries.iter().map(|entry| entry.name.as_str()).collect();
+        assert_eq!(names, vec!["Z-dir", "b-dir", "a.txt", "c.txt"]);
+
+        assert_eq!(read(&root, &[], true).unwrap().len(), 5);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn format_fields() {
+        assert_eq!(format_size(0), "0 B");
+        assert_eq!(format_size(1023), "1023 B");
+        assert_eq!(format_size(1536), "1.5 KiB");
+        assert_eq!(format_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        let time = UNIX_EPOCH + std::time::Duration::from_secs(784111777);
+        assert_eq!(format_time(time), "1994-11-06 08:49");
+    }
+
+    #[test]
+    fn json_schema() {
+        let root = std::env::temp_dir().join(format!("listing-json-{}", process::id()));
+        fs::remove_dir_all(&root).ok();
+        fs::create_dir_all(root.join("sub")).unwrap();
+        fs::write(root.join("notes.txt"), "hello").unwrap();
+
+        let mut entries = read(&root, &[Vec::from("docs")], true).unwrap();
+        sort(&mut entries, SortKey::Name, false);
+        let dir = to_api_json(&entries[0]);
+        let file = to_api_json(&entries[1]);
+        let mut keys: Vec<&String> = file.as_object().unwrap().keys().collect();
+        keys.sort();
+        assert_eq!(
+            keys,
+            ["etag", "mime", "mtime", "name", "path", "size", "type"]
+        );
+
+        assert_eq!(file["name"], "notes.txt");
+        assert_eq!(file["path"], "/docs/notes.txt");
+        assert_eq!(file["type"], "file");
+        assert_eq!(file["size"], 5);
+        assert!(file["mtime"].as_u64().unwrap() > 0);
+        assert_eq!(file["mime"], "text/plain");
+        assert!(file["etag"].as_str().unwrap().starts_with('"'));
+
+        assert_eq!(dir["name"], "sub");
+        assert_eq!(dir["path"], "/docs/sub");
+        assert_eq!(dir["type"], "dir");
+        assert_eq!(dir["size"], Value::Null);
+        assert_eq!(dir["mime"], Value::Null);
+        assert_eq!(dir["etag"], Value::Null);
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn negotiate_json() {
+        let request = |path: &str, accept: Option<&str>| {
+            let mut request = Request {
+                method: String::from("GET"),
+                path: String::from(path),
+                url: Url::parse(path.as_bytes()).unwrap(),
+                version: String::from("HTTP/1.1"),
+                headers: Default::default(),
+                body: Vec::new(),
+                body_reader: None,
+            };
+            if let Some(accept) = accept {
+                request
+                    .headers
+                    .insert(String::from("Accept"), String::from(accept));
+            }
+            wants_json(&request)
+        };
+        assert!(request("/?format=json", None));
+        assert!(!request("/?format=html", Some("application/json")));
+        assert!(request("/", Some("application/json")));
+        assert!(request("/", Some("text/html;q=0.5, application/json")));
+        assert!(!request(
+            "/",
+            Some("text/html,application/xhtml+xml,*/*;q=0.8")
+        ));
+        assert!(!request("/", Some("application/json;q=0")));
+        assert!(!request("/", None));
+    }
+}
@@ -1,223 +1,815 @@
-use handlebars::Handlebars;
 use serde_json::json;
-use std::{borrow::Borrow,  fs, path::{Path, PathBuf},  sync::Arc};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    env,
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    process,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::{Duration, UNIX_EPOCH},
+};
 
+use super::{
+    archive::{self, Format},
+    assets, compression, file_ops,
+    i18n::Locale,
+    listing::{self, SortKey},
+    preview,
+    templates::Templates,
+    thumbnail, tus,
+};
 use crate::infra::http::{
-    form_data::FormData,
-    message::{HandleFn, HttpMessage,  Response},
+    form_data::{FormData, MultipartReader},
+    header::{self, ByteRange},
+    message::{ExpectFn, HandleFn, HttpMessage, Request, Response},
     method::{self, Method},
-    mime, status,
+    mime,
+    status::{self, Status},
+    url::Url,
 };
 
-#[derive(PartialEq)]
-enum DirEntryType {
-    File,
-    Dir,
-    Symlink,
-    Unknown,
+/// text fields are held in memory, so their size is capped
+const MAX_FIELD_SIZE: u64 = 64 * 1024;
+
+/// bytes looked at to guess the type of a file without a known extension
+const SNIFF_SIZE: usize = 512;
+
+#[derive(Debug, Clone)]
+pub struct StaticOptions {
+    /// largest accepted size of a single uploaded part in bytes
+    pub max_part_size: u64,
+    /// where resumable uploads are kept until complete, outside the served root
+    pub staging_dir: PathBuf,
+    /// how long an unfinished resumable upload is kept after its last chunk
+    pub upload_expiration: Duration,
+    /// whether PUT creates missing parent directories
+    pub create_parents: bool,
+    /// whether dotfiles are listed and put in archives
+    pub show_hidden: bool,
+    /// largest total size of the files in a downloaded directory archive
+    pub max_archive_size: u64,
+    /// entries shown on one page of a directory listing
+    pub page_size: usize,
+    /// files served for a directory request, the first one found wins
+    pub index_files: Vec<String>,
+    /// whether directories without an index file are listed
+    pub auto_index: bool,
+    /// served instead of the not found page, relative to the root
+    pub spa_fallback: Option<PathBuf>,
+    /// `*.hbs` files replacing or adding to the built-in templates
+    pub templates_dir: Option<PathBuf>,
+    /// files served below `/.assets/`, in front of the built-in stylesheet and icons
+    pub assets_dir: Option<PathBuf>,
+    /// whether templates are read again for every page, for working on them
+    pub hot_reload: bool,
+    /// whether text bodies are compressed on the fly, precompressed siblings are served regardless
+    pub compression: bool,
+    /// bodies below this size in bytes are sent uncompressed
+    pub compress_min_size: usize,
+    /// whether browsers get Markdown rendered and source code highlighted instead of the file
+    pub preview: bool,
+    /// where image thumbnails are cached, outside the served root
+    pub thumbnail_dir: PathBuf,
 }
 
-pub fn static_middleware(root: String) -> HandleFn {
-    let mut reg = Handlebars::new();
-    reg.register_template_string(
-        "layout",
-        "<!DOCTYPE html>
-<html lang=\"en\">
-<head>
-    <meta charset=\"UTF-8\">
-    <meta http-equiv=\"X-UA-Compatible\" content=\"IE=edge\">
-    <meta name=\"viewport\" content=\"width=device-width, initial-scale=1.0\">
-    <title>{{ title }}</title>
-</head>
-    <body>
-        {{> @partial-block }}
-    </body>
-</html>",
-    )
-    .unwrap();
-    reg.register_template_string(
-        "not_found",
-        "{{#> layout title=\"Not Found\" }}
-<h1>Not Found {{path}}</h1>        
-{{/layout}}",
+impl StaticOptions {
+    pub fn new() -> Self {
+        Self {
+            max_part_size: u64::MAX,
+            staging_dir: env::temp_dir().join("https-server-app-uploads"),
+            upload_expiration: Duration::from_secs(24 * 60 * 60),
+            create_parents: false,
+            show_hidden: true,
+            max_archive_size: u64::MAX,
+            page_size: 500,
+            index_files: vec![String::from("index.html")],
+            auto_index: true,
+            spa_fallback: None,
+            templates_dir: None,
+            assets_dir: None,
+            hot_reload: false,
+            compression: true,
+            compress_min_size: 1024,
+            preview: false,
+            thumbnail_dir: env::temp_dir().join("https-server-app-thumbnails"),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn segment_to_os(segment: &[u8]) -> std::ffi::OsString {
+    use std::os::unix::ffi::OsStrExt;
+    std::ffi::OsStr::from_bytes(segment).to_os_string()
+}
+
+#[cfg(not(unix))]
+fn segment_to_os(segment: &[u8]) -> std::ffi::OsString {
+    std::ffi::OsString::from(String::from_utf8_lossy(segment).into_owned())
+}
+
+/// Appends decoded segments to `path`, refusing anything that could leave it
+pub fn push_segments<'a>(
+    mut path: PathBuf,
+    segments: impl IntoIterator<Item = &'a [u8]>,
+) -> Option<PathBuf> {
+    for segment in segments {
+        match segment {
+            b"" | b"." => {}
+            b".." => return None,
+            segment if segment.iter().any(|byte| matches!(byte, b'/' | b'\\' | 0)) => return None,
+            segment => path.push(segment_to_os(segment)),
+        }
+    }
+    Some(path)
+}
+
+/// Maps the decoded url segments onto `root`, refusing anything that could leave it
+fn resolve_path(root: &str, url: &Url) -> Option<PathBuf> {
+    push_segments(
+        PathBuf::from(root),
+        url.segments.iter().map(|segment| segment.as_slice()),
     )
-    .unwrap();
-    reg.register_template_string(
-        "index",
-        "{{#> layout title=path }}
-<script>
-function deleteFile(filepath) {
-    if(!confirm('Sure To Delete '+filepath)) return;
-    var xhr = new XMLHttpRequest();
-    if(onload) {
-        xhr.onload = onload;
+}
+
+/// Creates a hidden temp file next to `path` so the final rename stays on one filesystem
+pub fn create_temp_file(path: &Path) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    loop {
+        let temp_path = path.with_file_name(format!(
+            ".{}.upload-{}-{}",
+            name,
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        match OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&temp_path)
+        {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Streams `reader` into a temp file and renames it onto `path` once complete
+fn write_file<R: Read>(
+    reader: R,
+    path: &Path,
+    options: &StaticOptions,
+    overwrite: bool,
+) -> Result<(), Response> {
+    let conflict = || Response::with_text(status::CONFLICT, "file already exists");
+    if !overwrite && path.exists() {
+        return Err(conflict());
+    }
+    let (temp_path, mut file) = create_temp_file(path)
+        .map_err(|err| Response::with_text(status::INTERNAL_SERVER_ERROR, &err.to_string()))?;
+    let limit = options.max_part_size;
+    let copied = io::copy(&mut reader.take(limit.saturating_add(1)), &mut file);
+    drop(file);
+    let response = match copied {
+        Ok(size) if size > limit => Some(Response::with_text(
+            status::PAYLOAD_TOO_LARGE,
+            "file too large",
+        )),
+        Ok(_) => None,
+        Err(err) if err.kind() == io::ErrorKind::InvalidData => {
+            Some(Response::with_text(status::BAD_REQUEST, &err.to_string()))
+        }
+        Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Some(Response::with_text(
+            status::BAD_REQUEST,
+            "incomplete request body",
+        )),
+        Err(err) => Some(Response::with_text(
+            status::INTERNAL_SERVER_ERROR,
+            &err.to_string(),
+        )),
+    };
+    if let Some(response) = response {
+        fs::remove_file(&temp_path).ok();
+        return Err(response);
     }
-    xhr.open(\"delete\",filepath,true);
-    xhr.send(null);
-    xhr.onload = function(){
-        location.reload();
+    if !overwrite {
+        // a hard link fails when the target appeared meanwhile, unlike rename
+        match fs::hard_link(&temp_path, path) {
+            Ok(_) => {
+                fs::remove_file(&temp_path).ok();
+                return Ok(());
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {
+                fs::remove_file(&temp_path).ok();
+                return Err(conflict());
+            }
+            // filesystems without hard links fall back to the check above
+            Err(_) => {}
+        }
+    }
+    if let Err(err) = fs::rename(&temp_path, path) {
+        fs::remove_file(&temp_path).ok();
+        return Err(Response::with_text(
+            status::INTERNAL_SERVER_ERROR,
+            &err.to_string(),
+        ));
     }
+    Ok(())
+}
+
+/// dotfiles, including the temp files of unfinished uploads
+pub fn is_hidden(name: &str) -> bool {
+    name.starts_with('.')
 }
-function uploadFile(filepath,file,onload){
-    if(!confirm('Sure To Upload '+filepath)) return;
-    var xhr = new XMLHttpRequest();
-    if(onload) {
-        xhr.onload = onload
+
+/// Last component of a client supplied filename, some browsers send the full local path
+pub fn upload_file_name(filename: &str) -> Option<&str> {
+    match filename.rsplit(['/', '\\']).next() {
+        Some("") | Some(".") | Some("..") | None => None,
+        Some(name) if name.contains('\0') => None,
+        Some(name) => Some(name),
     }
-    xhr.open(\"post\",filepath,true);
-    xhr.setRequestHeader(\"Content-Type\",\"multipart/form-data; boundary=----WebKitFormBoundaryyb1zYhTI38xpQxBK\");
-    var formData = new FormData();
-    formData.append(\"file\", file);
-    xhr.send(formData);
 }
-</script>
-<h1>Index {{path}}</h1>
-<ul>
-    <li>
-        <a href=\"../\">../</a>
-    </li>
-    {{#each files as |f|}}
-        <li>
-            <button onclick=\"deleteFile('{{f.[1]}}')\" >delete</button>
-            <img width=\"20\" src=\"{{f.[2]}}\" />
-            <a href=\"{{f.[1]}}\" >{{f.[0]}}</a>
-        </li>
-    {{/each}}
-</ul>
-<input id=\"file\" type=\"file\" />
-<button id=\"upload\" disabled >上传</button>
-<script>
-var fileInput = document.getElementById(\"file\");
-var uploadBtn = document.getElementById(\"upload\")
-fileInput.addEventListener(\"change\",function(){
-    uploadBtn.disabled=false;
-});
-uploadBtn.addEventListener(\"click\",function(){
-    var file = fileInput.files.length && fileInput.files[0];
-    if(!file) return;
-    var prefix = \"{{path}}\".replace(/\\/$/,\"\");
-    uploadFile(prefix+\"/\"+file.name,file, function() {
-        fileInput.value = '';
-        location.reload();
+
+/// Whether `path` is in a top level directory of `root` whose url the server answers itself,
+/// `/.tus/` or `/.assets/`, nothing placed there could be reached
+pub fn is_reserved(root: &Path, path: &Path) -> bool {
+    match path.strip_prefix(root).ok().and_then(|rest| rest.components().next()) {
+        Some(Component::Normal(name)) => {
+            [tus::TUS_PREFIX, assets::ASSETS_PREFIX].contains(&name.as_encoded_bytes())
+        }
+        _ => false,
+    }
+}
+
+/// Resolves the `dir` of an upload below `base`, creating missing directories when `parents` is set
+pub fn upload_directory(
+    root: &Path,
+    base: &Path,
+    dir: &[u8],
+    parents: bool,
+) -> Result<PathBuf, (Status, String)> {
+    let directory = push_segments(PathBuf::from(base), dir.split(|byte| *byte == b'/'))
+        .ok_or((status::FORBIDDEN, String::from("forbidden")))?;
+    if is_reserved(root, &directory) {
+        return Err((status::FORBIDDEN, String::from("reserved name")));
+    }
+    if parents && !directory.exists() {
+        fs::create_dir_all(&directory)
+            .map_err(|err| (status::INTERNAL_SERVER_ERROR, err.to_string()))?;
+    }
+    if !directory.is_dir() {
+        return Err((status::NOT_FOUND, String::from("directory not found")));
+    }
+    Ok(directory)
+}
+
+/// Streams the file parts of a multipart body to disk, each target only appears once complete.
+///
+/// Posting to a directory saves every file part under its own filename, the text fields
+/// `dir` (a subdirectory), `parents` (create `dir` when missing) and `overwrite` apply to
+/// the file parts that follow them. A file that fails does not stop the others, the answer
+/// lists both as `{"ok":false,"saved":["a.txt"],"failed":[{"name":"b.txt","error":"..."}]}`.
+/// Posting to any other path saves the single file part there.
+fn save_upload(request: &Request, root: &Path, path: &Path, options: &StaticOptions) -> Response {
+    let boundary = match FormData::boundary(request.get_header("Content-Type").unwrap_or("")) {
+        Ok(boundary) => boundary,
+        Err(err) => return Response::with_text(status::BAD_REQUEST, &err.to_string()),
+    };
+    let body = match request.body_reader.clone() {
+        Some(body) => body,
+        None => return Response::with_text(status::BAD_REQUEST, "missing request body"),
+    };
+    let to_directory = path.is_dir();
+    let mut directory = Ok(PathBuf::from(path));
+    let mut dir_name = String::new();
+    let mut parents = false;
+    let mut overwrite = true;
+    let mut saved = Vec::new();
+    let mut failed = Vec::new();
+    let mut failed_code = None;
+    let mut reader = MultipartReader::new(body, boundary.as_bytes());
+    loop {
+        let mut part = match reader.next_part() {
+            Ok(Some(part)) => part,
+            Ok(None) => break,
+            Err(err) => return Response::with_text(status::BAD_REQUEST, &err.to_string()),
+        };
+        let filename = part.info.filename.clone();
+        // a `file` part without filename still counts as a file, as the original form sent it
+        if filename.is_none() && part.info.name.as_deref() != Some("file") {
+            let mut value = Vec::new();
+            match (&mut part).take(MAX_FIELD_SIZE + 1).read_to_end(&mut value) {
+                Ok(size) if size as u64 > MAX_FIELD_SIZE => {
+                    return Response::with_text(status::PAYLOAD_TOO_LARGE, "field too large")
+                }
+                Ok(_) => {}
+                Err(err) => return Response::with_text(status::BAD_REQUEST, &err.to_string()),
+            }
+            match part.info.name.as_deref() {
+                Some("dir") if to_directory => {
+                    directory = upload_directory(root, path, &value, parents);
+                    dir_name = String::from_utf8_lossy(&value).trim_matches('/').to_string();
+                }
+                Some("parents") => {
+                    parents = matches!(value.as_slice(), b"1" | b"true" | b"on");
+                }
+                Some("overwrite") => {
+                    overwrite = matches!(value.as_slice(), b"1" | b"true" | b"on");
+                }
+                _ => {}
+            }
+            continue;
+        }
+        if !to_directory {
+            if !saved.is_empty() {
+                return Response::with_text(status::BAD_REQUEST, "multiple files for one path");
+            }
+            if let Err(response) = write_file(part, path, options, overwrite) {
+                return response;
+            }
+            saved.push(filename.unwrap_or_default());
+            continue;
+        }
+        let name = filename.as_deref().and_then(upload_file_name);
+        let display_name = match name {
+            Some(name) if !dir_name.is_empty() => format!("{}/{}", dir_name, name),
+            Some(name) => String::from(name),
+            None => filename.clone().unwrap_or_default(),
+        };
+        let written = match (&directory, name) {
+            (Err(err), _) => Err(err.clone()),
+            (Ok(_), None) => Err((status::BAD_REQUEST, String::from("invalid filename"))),
+            (Ok(directory), Some(name)) if is_reserved(root, &directory.join(name)) => {
+                Err((status::FORBIDDEN, String::from("reserved name")))
+            }
+            (Ok(directory), Some(name)) => {
+                write_file(part, &directory.join(name), options, overwrite).map_err(|response| {
+                    let error = String::from_utf8_lossy(&response.body).into_owned();
+                    (response.code, error)
+                })
+            }
+        };
+        match written {
+            Ok(_) => saved.push(display_name),
+            // a broken body leaves nothing to read the other files from
+            Err((code, error)) if code == status::BAD_REQUEST && name.is_some() => {
+                return Response::with_text(code, &error)
+            }
+            Err((code, error)) => {
+                failed_code.get_or_insert(code);
+                failed.push(json!({ "name": display_name, "error": error }));
+            }
+        }
+    }
+    if !to_directory || (saved.is_empty() && failed.is_empty()) {
+        return match saved.is_empty() {
+            true => Response::with_text(status::BAD_REQUEST, "missing file part"),
+            false => Response::with_text(status::OK, "ok"),
+        };
+    }
+    let mut response = Response::new();
+    // some files made it, so the request as a whole did something
+    response.set_code(match saved.is_empty() {
+        true => failed_code.unwrap_or(status::BAD_REQUEST),
+        false => status::OK,
     });
-});
-</script>
-{{/layout}}",
-    )
-    .unwrap();
-    reg.register_template_string(
-        "unknown",
-        "{{#> layout title=\"Not Found\" }}
-<h1>Unknown Forbidden {{path}}</h1>
-{{/layout}}",
-    )
-    .unwrap();
+    response.set_header("Content-Type", "application/json");
+    let summary = json!({ "ok": failed.is_empty(), "saved": saved, "failed": failed });
+    response.set_body(&Vec::from(summary.to_string()));
+    response
+}
 
-    Box::new(Arc::new(move |request| -> Response {
+/// Strong validator from the modification time and the size
+pub fn etag(metadata: &fs::Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .unwrap_or(0);
+    format!("\"{:x}-{:x}\"", modified, metadata.len())
+}
+
+/// Tells caches that the body depends on the request header `field`, keeping what `Vary` held
+pub fn add_vary(response: &mut Response, field: &str) {
+    let vary = match response.get_header("Vary") {
+        Some(vary) if vary.split(',').any(|name| name.trim().eq_ignore_ascii_case(field)) => return,
+        Some(vary) => format!("{}, {}", vary, field),
+        None => String::from(field),
+    };
+    response.set_header("Vary", &vary);
+}
+
+/// Answers a GET for a file with its content, or with the single byte range it asks for.
+/// A precompressed `.br` or `.gz` sibling is sent in its place when the client accepts it.
+pub fn serve_file(request: &Request, path: &Path, info: &fs::Metadata) -> Response {
+    let siblings = compression::siblings(path, info);
+    let encoding = compression::negotiate(
+        request.get_header("Accept-Encoding"),
+        siblings.iter().map(|(encoding, _, _)| *encoding),
+    );
+    let sibling = siblings
+        .iter()
+        .find(|(sibling_encoding, _, _)| Some(*sibling_encoding) == encoding);
+    let (body, tag) = match sibling {
+        Some((encoding, sibling_path, sibling_info)) => (
+            fs::read(sibling_path),
+            compression::encoded_etag(&etag(sibling_info), *encoding),
+        ),
+        None => (fs::read(path), etag(info)),
+    };
+    let body = match body {
+        Ok(body) => body,
+        Err(_) => return Response::with_text(status::FORBIDDEN, "<h1>Forbidden</h1>"),
+    };
+    let mut response = Response::new();
+    response.set_header("ETag", &tag);
+    response.set_header("Accept-Ranges", "bytes");
+    if let Some(encoding) = encoding {
+        response.set_header("Content-Encoding", encoding.token());
+    }
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    // the type of a precompressed file is guessed from the original, not from the encoded bytes
+    let head = match encoding {
+        None => body[..body.len().min(SNIFF_SIZE)].to_vec(),
+        Some(_) => File::open(path)
+            .and_then(|file| {
+                let mut head = Vec::new();
+                file.take(SNIFF_SIZE as u64).read_to_end(&mut head)?;
+                Ok(head)
+            })
+            .unwrap_or_default(),
+    };
+    let content_type = mime::content_type(extension, &head);
+    // a range is never compressed on the fly, but the whole file may be, so both vary alike
+    if !siblings.is_empty()
+        || content_type
+            .as_deref()
+            .is_some_and(compression::is_compressible)
+    {
+        add_vary(&mut response, "Accept-Encoding");
+    }
+    if content_type.is_none() {
+        let filename = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        response.set_header(
+            "Content-Disposition",
+            &format!(
+                "attachment; filename={}; filename*={}",
+                header::quote(&filename),
+                header::encode_ext_value(&filename)
+            ),
+        );
+    }
+    response.set_header(
+        "Content-Type",
+        content_type.as_deref().unwrap_or("application/octet-stream"),
+    );
+    // an If-Range naming another version asks for the whole new one
+    let range = request
+        .get_header("Range")
+        .filter(|_| request.get_header("If-Range").is_none_or(|condition| condition == tag))
+        .and_then(|range| header::parse_range(range, body.len() as u64));
+    match range {
+        Some(ByteRange::Satisfiable(start, end)) => {
+            response.set_code(status::PARTIAL_CONTENT);
+            response.set_header(
+                "Content-Range",
+                &format!("bytes {}-{}/{}", start, end, body.len()),
+            );
+            response.set_body(&body[start as usize..=end as usize]);
+        }
+        Some(ByteRange::Unsatisfiable) => {
+            response.set_code(status::RANGE_NOT_SATISFIABLE);
+            response.set_header("Content-Range", &format!("bytes */{}", body.len()));
+        }
+        None => response.set_body(&body),
+    }
+    response
+}
+
+/// Redirects `/docs` to `/docs/`, keeping the query
+fn redirect_to_directory(url: &Url) -> Response {
+    let mut location = format!("{}/", url.encoded_path());
+    if !url.query.pairs.is_empty() {
+        location.push('?');
+        location.push_str(&url.query.encode());
+    }
+    let mut response = Response::with_text(status::MOVED_PERMANENTLY, "");
+    response.set_header("Location", &location);
+    response
+}
+
+/// Evaluates an If-Match or If-None-Match list against the current entity tag, see RFC 9110 section 13.1
+fn etag_matches(condition: &str, current: Option<&str>, weak: bool) -> bool {
+    let current = match current {
+        Some(current) => current,
+        None => return false,
+    };
+    condition.split(',').map(|tag| tag.trim()).any(|tag| {
+        tag == "*" || tag == current || (weak && tag.strip_prefix("W/") == Some(current))
+    })
+}
+
+/// Answers 304 instead when the client already holds the representation of a GET
+fn not_modified(request: &Request, response: &mut Response) {
+    if request.method != "GET" || response.code != status::OK {
+        return;
+    }
+    let condition = match request.get_header("If-None-Match") {
+        Some(condition) => condition,
+        None => return,
+    };
+    if etag_matches(condition, response.get_header("ETag"), true) {
+        response.set_code(status::NOT_MODIFIED);
+        response.set_body(&Vec::new());
+    }
+}
+
+/// Writes the raw body to `path`, 201 when the file is new and 204 when it was replaced
+fn save_put(request: &Request, path: &Path, options: &StaticOptions) -> Response {
+    if path.is_dir() {
+        return Response::with_text(status::CONFLICT, "target is a directory");
+    }
+    let current = fs::metadata(path)
+        .ok()
+        .filter(|metadata| metadata.is_file())
+        .map(|metadata| etag(&metadata));
+    if let Some(condition) = request.get_header("If-Match") {
+        if !etag_matches(condition, current.as_deref(), false) {
+            return Response::with_text(status::PRECONDITION_FAILED, "precondition failed");
+        }
+    }
+    let if_none_match = request.get_header("If-None-Match");
+    if let Some(condition) = if_none_match {
+        if etag_matches(condition, current.as_deref(), true) {
+            return Response::with_text(status::PRECONDITION_FAILED, "precondition failed");
+        }
+    }
+    let content_length = request
+        .get_header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .unwrap_or(0);
+    if content_length > options.max_part_size {
+        return Response::with_text(status::PAYLOAD_TOO_LARGE, "file too large");
+    }
+    match path.parent() {
+        Some(parent) if parent.is_dir() => {}
+        Some(parent) if options.create_parents => {
+            if let Err(err) = fs::create_dir_all(parent) {
+                return Response::with_text(status::CONFLICT, &err.to_string());
+            }
+        }
+        _ => return Response::with_text(status::NOT_FOUND, "directory not found"),
+    }
+
+    // `If-None-Match: *` asks to create only, so it must not replace a file that appears meanwhile
+    let overwrite = if_none_match.map(|condition| condition.trim()) != Some("*");
+    let written = match request.body_reader.clone() {
+        Some(body) => write_file(body, path, options, overwrite),
+        None => write_file(&request.body[..], path, options, overwrite),
+    };
+    if let Err(response) = written {
+        return response;
+    }
+    let mut response = Response::with_text(
+        if current.is_some() {
+            status::NO_CONTENT
+        } else {
+            status::CREATED
+        },
+        "",
+    );
+    if let Ok(metadata) = fs::metadata(path) {
+        response.set_header("ETag", &etag(&metadata));
+    }
+    response
+}
+
+/// Rejects uploads from their headers alone, before the client sends the body
+pub fn static_expect(root: String, options: StaticOptions) -> ExpectFn {
+    let check = move |request: &Request| -> Option<Response> {
+        if tus::is_tus(&request.url) || file_ops::action(request).is_some() {
+            return None;
+        }
+        match method::get_methods(request.method.as_str()) {
+            Some(Method::Post) | Some(Method::Put) => {}
+            _ => {
+                return Some(Response::with_text(
+                    status::METHOD_NOT_ALLOWED,
+                    "method not allowed",
+                ))
+            }
+        }
+        // a PUT body is the file itself, so an oversized one is refused before it is sent.
+        // a form may carry several files below the limit each, its parts are checked while read.
+        let length = request
+            .get_header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok());
+        if request.method == "PUT" && length.is_some_and(|length| length > options.max_part_size) {
+            return Some(Response::with_text(status::PAYLOAD_TOO_LARGE, "file too large"));
+        }
+        let path = match resolve_path(&root, &request.url) {
+            Some(path) => path,
+            None => return Some(Response::with_text(status::FORBIDDEN, "forbidden")),
+        };
+        // a POST to a directory saves its files inside it, `.` as root has no parent to look at
+        if request.method == "POST" && path.is_dir() {
+            return None;
+        }
+        match path.parent() {
+            Some(parent) if parent.is_dir() => None,
+            Some(_) if options.create_parents && request.method == "PUT" => None,
+            _ => Some(Response::with_text(status::NOT_FOUND, "directory not found")),
+        }
+    };
+    Box::new(Arc::new(move |request| -> Option<Response> {
+        let mut response = check(request)?;
+        Locale::from_request(request).translate_response(&mut response);
+        Some(response)
+    }))
+}
+
+pub fn static_middleware(root: String, options: StaticOptions) -> HandleFn {
+    let templates = Templates::new(&options);
+    let compress_min_size = options.compression.then_some(options.compress_min_size);
+
+    let handle = move |request: Rc<RefCell<Request>>, locale: Locale| -> Response {
+        let action = file_ops::action(&(*request).borrow());
+        if let Some(action) = action {
+            return file_ops::handle(&mut (*request).borrow_mut(), &root, &action);
+        }
         let request = (*request).borrow();
+        if tus::is_tus(&request.url) {
+            return tus::handle(&request, &root, &options);
+        }
+        if assets::is_asset(&request.url) {
+            return assets::handle(&request, &options);
+        }
         match method::get_methods(request.method.as_str()) {
             Some(method) => match method {
                 Method::Get => {
                     let request = (*request).borrow();
-                    let index_path = request.path.clone().replace("../", "").to_string();
-                    let current_path = root.clone().to_string() + index_path.as_str();
+                    let index_path = request.url.path();
+                    let current_path = match resolve_path(&root, &request.url) {
+                        Some(path) => path,
+                        None => return Response::with_text(status::FORBIDDEN, "<h1>Forbidden</h1>"),
+                    };
                     let mut response = Response::new();
-                    response.set_header("Content-Type", "text/html;utf-8");
+                    response.set_header("Content-Type", "text/html; charset=utf-8");
                     match fs::metadata(&current_path) {
                         Err(_) => {
+                            // single-page apps route unknown paths themselves
+                            if let Some(fallback) = &options.spa_fallback {
+                                let fallback = Path::new(&root).join(fallback);
+                                if let Ok(info) = fs::metadata(&fallback) {
+                                    if info.is_file() {
+                                        return serve_file(request, &fallback, &info);
+                                    }
+                                }
+                            }
                             response.set_code(status::NOT_FOUND);
-                            let body = reg
-                                .render("not_found", &json!({ "path": index_path }))
-                                .unwrap();
+                            let body = templates.render("not_found", &json!({ "path": index_path }), locale);
                             response.set_body(&Vec::from(body.as_bytes()));
                             response
                         }
                         Ok(info) => {
                             if info.is_dir() {
-                                type DirEntryInfo = (String, String, &'static str,bool);
-                                let files = fs::read_dir(&current_path).unwrap();
-                                let files: Vec<DirEntryInfo> = files
-                                    .into_iter()
-                                    .map(|f| -> DirEntryInfo {
-                                        let f = f.unwrap();
-                                        let file_type = f.file_type();
-                                        // let metadata = f.metadata();
-                                        let file_name = f.file_name();
-            
-                                        let file_type = file_type.unwrap();
-                                        let file_type = if file_type.is_file() {
-                                            DirEntryType::File
-                                        } else if file_type.is_dir() {
-                                            DirEntryType::Dir
-                                        } else if file_type.is_symlink() {
-                                            DirEntryType::Symlink
-                                        } else {
-                                            DirEntryType::Unknown
-                                        };
-                                        let file_name = file_name.to_str().unwrap_or("");
-                                        (
-                                            String::from(file_name),
-                                            String::from(
-                                                Path::new("/")
-                                                    .join(&index_path)
-                                                    .join(file_name)
-                                                    .to_str()
-                                                    .unwrap_or(""),
-                                            ),
-                                            match file_type {
-                                                DirEntryType::File => {
-                                                    "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAABgAAAAYCAYAAADgdz34AAAABmJLR0QA/wD/AP+gvaeTAAAAcklEQVRIie3VQQqAIBCF4b/ocB6rZefUg9hGoURtZiIC8YG4cGY+VwojxgEBiA9rtwJeMPwVkpslNRE4vgbUiAVQIVagiiyN5tZZWVPLrW/rXVM6pIeuRkCcCUxgAj8BIe3ST+f6OAYEceh+tbx86h0sJ1orUB8gNFrWAAAAAElFTkSuQmCC"
-                                                },
-                                                DirEntryType::Dir => {
-                                                    "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAADIAAAAyCAYAAAAeP4ixAAAABmJLR0QA/wD/AP+gvaeTAAABCUlEQVRoge2ZTQ4BMRiGHyIkTuAUVjY4g4VD+VtZuZiVAzAcQWxY6MRkNLQM/cj7JF86aTrJ+3S+ZhYFIUQoTWABZMA5oDJgnCTpE+aECRTrBPRShH3Ejmu4fuD6pVu/AdqfCvUK+S6H0gLW7p3VRxK9SKwIQBc4Et+S79YOmHE915WIAIy4teW3a1qlSAoG3L7MHb8kAqW89YRBKqXhmdsDnW8HieRQnqgVnn+prYrUyhN5z4X+EFOSH3bv5uuwW0Ai1pCINSRiDYlYQyLWkIg1JGINiVhDItaQiDUkYg2JWOMvRTI3DlIEiWToRu9Fz4w012fv1MQn0nQyqe4DY2rrJLyXoUKIey7M1NDZgDzGvQAAAABJRU5ErkJggg=="
-                                                },
-                                                DirEntryType::Symlink => {
-                                                    "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAADIAAAAyCAYAAAAeP4ixAAAABmJLR0QA/wD/AP+gvaeTAAACDElEQVRoge2Yu0rEQBSGv/UComLngloJa2ch1t5gn0DQUixsvFQ+gXaCla1PIAj6ACouaqFiIbp46XwCLRQsVlGLnGC87SZzy7jkQJjM7Jl//i+TmSQLWfyveLd43ABd9QDiFCYc0JauMxjbIOVImbcwzo8Bbel2Apc4mBnbIOAIxgUIOIBxBQKWYVyCgEUY1yBgCSYNELAAkxYIGIZx8WSPe5SrCeZiDBgnL2moXpw/fTQpCupG0gtTE7xB0Yh3kYH4FrZA9om/Gx1Z8vAlVLffU5JtrbZ8aAvEfZh5DwLBF9+V9D/T1K+ZZ3OxDwDdcv5qcZxYoTojC8CL9N0A2jX1U7m1poE3gllYNKTvHGQUqEif+V9+7ydYL8cEa8hLkGbgVvJXfvl9BniOaJbxFGRScq8JoMLIAasRrW2+QngHsim5c9/a16T9DVgmAMvj8YzcSW5fpG1W2irAxLf8PHAC7Br2oS0Q3v8tUu8BHqVtSsdEQh/aAqHpDqkvSX1Lx4CCD22BcMcalPq51Md0DMT1YfIV5VDKcSl7pTw3OIZyJJmRouQ+ECzkJ6m3OvZhRKAk+TvAhZwP6xhQ9KEtUADupc+rlLtAo44JBR9GBIb4hAmPPWAEaHPow4hAgb+/20sOfRgTKALrBO9f4eI/SMGHvoChSPVT12lkIL5F3H/jfVgnVaNuZiQL3+ID+YBWVoOW43UAAAAASUVORK5CYII="
-                                                },
-                                                DirEntryType::Unknown => {
-                                                    "data:image/png;base64,iVBORw0KGgoAAAANSUhEUgAAADIAAAAyCAYAAAAeP4ixAAAABmJLR0QA/wD/AP+gvaeTAAACXUlEQVRoge3ZPWsUQRzH8c/FCBKIlYVYKKiIGnxAlCiC+FRoEcVKjC9AEMGARHwLYuML0BdhYyoLHwIS0SKYJmiTNPGBKEiiQfEslkQNO7s7d7e3F7gvTDPH/v6/397s7MxsTWvpwSDO4RS2YDN68QWTeINHeNni2i1hHYYxhXrB9hZXUavAbyrbMaF4gNXtKXa03fUqLuKrxkMst0843mbvK1zCzxyDMe07jrU1AfZhoUnjaW0O29oVoobxHEOTuI7d6EM/jmAU0znXjrUryJUME4u4Jnsm6sVt2cNyqCTvK/TgfaD4Es5GaF0QDvOsdZbTOR0oXMfNBvTuBLR+Y2cL/Aa5Fyg8JRkysazHu4DmSIxQT2Tho4H+h/gVqUUytB4EfjvYgF5h5qTfvYEmNAcDmq+acprDUqBofxOamwKa0zEisUPrY0rfLL5F6vxLPdC/oQnNXM5jxt+7NiNZsjfDYen/yOsYkdiZZgxbI6/J40ygf7bFdUqlV3j6Ha3QVzShF2Id+yv0FcWQ8BJlokJfhenBLdmLxsuVuSvIIcmhQ9Yy/okO2senMSJ/R/lZB+zfs7grO0AdP3CiKoNFuCE/xDxOVmWwCAOSQ4WsEM+xqyqDRXksHGBBsq/v6AcbDgiHmMWe6qzFEXrAFyXT8JohdIx6v0pTsdQk02lakL0V+opmo/QQSxo7pMgldodYlL5A/7zGDilyKStI2+kG6TS6QTqNsoLMS97gq1lTJyPLDOOD/08OQ2fHXbp0WcOkffl9UVaxMreZoc8FpdTsvhA7jW6QAoyn9JX2sP8BoWVXVMudA50AAAAASUVORK5CYII="
-                                                }
-                                            },
-                                            file_type == DirEntryType::File || file_type == DirEntryType::Dir,
-                                        )
-                                    })
+                                if let Some(format) = request.url.query.get("archive") {
+                                    if !options.auto_index {
+                                        return Response::with_text(status::FORBIDDEN, "<h1>Forbidden</h1>");
+                                    }
+                                    let hidden = request.url.segments.iter().any(|segment| segment.starts_with(b"."));
+                                    if hidden && !options.show_hidden {
+                                        return Response::with_text(status::NOT_FOUND, "not found");
+                                    }
+                                    return match Format::from_query(format) {
+                                        Some(format) => archive::handle(&current_path, format, &options),
+                                        None => Response::with_text(status::BAD_REQUEST, "unknown archive format"),
+                                    };
+                                }
+                                let json = listing::wants_json(request);
+                                if !json {
+                                    // relative links in the page resolve against the directory only with the slash
+                                    if !request.url.segments.last().is_none_or(|segment| segment.is_empty()) {
+                                        return redirect_to_directory(&request.url);
+                                    }
+                                    for name in &options.index_files {
+                                        let index = current_path.join(name);
+                                        if let Ok(info) = fs::metadata(&index) {
+                                            if info.is_file() {
+                                                return serve_file(request, &index, &info);
+                                            }
+                                        }
+                                    }
+                                }
+                                if !options.auto_index {
+                                    return Response::with_text(status::FORBIDDEN, "<h1>Forbidden</h1>");
+                                }
+                                let segments: Vec<Vec<u8>> = request
+                                    .url
+                                    .segments
+                                    .iter()
+                                    .filter(|segment| !segment.is_empty())
+                                    .cloned()
                                     .collect();
-                                let body = reg
-                                    .render("index", &json!({ "path":index_path, "files": files }))
-                                    .unwrap();
+                                let mut entries = match listing::read(&current_path, &segments, options.show_hidden) {
+                                    Ok(entries) => entries,
+                                    Err(err) => return Response::with_text(status::INTERNAL_SERVER_ERROR, &err.to_string()),
+                                };
+                                let sort_key = request.url.query.get("sort").and_then(SortKey::from_query).unwrap_or(SortKey::Name);
+                                let descending = request.url.query.get("order") == Some("desc");
+                                listing::sort(&mut entries, sort_key, descending);
+                                response.set_header("Vary", "Accept");
+                                if json {
+                                    let entries: Vec<serde_json::Value> = entries.iter().map(listing::to_api_json).collect();
+                                    let body = json!({ "path": request.url.encoded_path(), "entries": entries });
+                                    response.set_header("Content-Type", "application/json");
+                                    response.set_body(&Vec::from(body.to_string()));
+                                    return response;
+                                }
+
+                                // large directories are split into pages, each keeping the sort order
+                                let page_size = options.page_size.max(1);
+                                let pages = entries.len().div_ceil(page_size).max(1);
+                                let page = request.url.query.get("page").and_then(|page| page.parse().ok()).unwrap_or(1).clamp(1, pages);
+                                let order = if descending { "desc" } else { "asc" };
+                                // the gallery shows the same entries as tiles, links keep the view
+                                let gallery = request.url.query.get("view").is_some_and(|view| view == "gallery");
+                                let view = if gallery { "&view=gallery" } else { "" };
+                                let page_href = |page: usize| format!("?sort={}&order={}&page={}{}", sort_key.name(), order, page, view);
+                                let files: Vec<serde_json::Value> = entries
+                                    .iter()
+                                    .skip((page - 1) * page_size)
+                                    .take(page_size)
+                                    .map(listing::to_json)
+                                    .collect();
+                                let mut columns = serde_json::Map::new();
+                                for key in SortKey::ALL {
+                                    let active = key == sort_key;
+                                    let next = if active && !descending { "desc" } else { "asc" };
+                                    columns.insert(String::from(key.name()), json!({
+                                        "href": format!("?sort={}&order={}{}", key.name(), next, view),
+                                        "arrow": match (active, descending) {
+                                            (false, _) => "",
+                                            (true, false) => "\u{25b2}",
+                                            (true, true) => "\u{25bc}",
+                                        },
+                                    }));
+                                }
+                                let pagination = json!({
+                                    "page": page,
+                                    "pages": pages,
+                                    "total": entries.len(),
+                                    "prev": if page > 1 { Some(page_href(page - 1)) } else { None },
+                                    "next": if page < pages { Some(page_href(page + 1)) } else { None },
+                                });
+                                let views = json!({
+                                    "list": format!("?sort={}&order={}", sort_key.name(), order),
+                                    "gallery": format!("?sort={}&order={}&view=gallery", sort_key.name(), order),
+                                });
+                                let body = templates.render("index", &json!({ "path":index_path, "base": request.url.encoded_path(), "files": files, "columns": columns, "pagination": pagination, "gallery": gallery, "views": views }), locale);
                                 response.set_body(&Vec::from(body));
                             } else if info.is_file() {
-                                let path = Path::new(&current_path);
-                                let body = fs::read_to_string(&current_path).unwrap();
-                                let content_type = mime::get_mime(if let Some(str) = path.extension() {
-                                    str.to_str().unwrap_or("")
-                                } else {
-                                    ""
-                                });
-                                if content_type.is_none() {
-                                    response.set_header(
-                                        "Content-Disposition",
-                                        format!(
-                                            "attachment; filename=\"{}\"",
-                                            if let Some(str) = path.file_name() {
-                                                str.to_str().unwrap_or("")
-                                            } else {
-                                                ""
-                                            }
-                                        )
-                                        .as_str(),
-                                    );
+                                if request.url.query.get("thumbnail").is_some() {
+                                    return thumbnail::handle(request, &current_path, &info, &options);
                                 }
-                                response.set_header(
-                                    "Content-Type:",
-                                    content_type.unwrap_or("application/octet-stream"),
-                                );
-            
+                                if !options.preview || preview::kind(&current_path).is_none() {
+                                    return serve_file(request, &current_path, &info);
+                                }
+                                // the same url answers a browser with a page and anything else with the file
+                                let rendered = match preview::wants_preview(request) {
+                                    true => preview::render(&current_path, &info),
+                                    false => None,
+                                };
+                                let (kind, content) = match rendered {
+                                    Some(rendered) => rendered,
+                                    None => {
+                                        let mut response = serve_file(request, &current_path, &info);
+                                        add_vary(&mut response, "Accept");
+                                        return response;
+                                    }
+                                };
+                                response.set_header("Vary", "Accept");
+                                let body = templates.render("preview", &json!({ "path": index_path, "kind": kind.name(), "content": content }), locale);
                                 response.set_body(&Vec::from(body));
                             } else {
-                                let body = reg
-                                    .render("unknown", &json!({ "path": index_path }))
-                                    .unwrap();
+                                let body = templates.render("unknown", &json!({ "path": index_path }), locale);
                                 response.set_body(&Vec::from(body));
                             }
                             response
@@ -226,15 +818,26 @@ uploadBtn.addEventListener(\"click\",function(){
                 },
                 Method::Post => {
                     let request = (*request).borrow();
-                    let data = FormData::parse(&request.body).unwrap().unwrap();
-                    let file = &data.get_part("file").unwrap().data;
-                    let path = PathBuf::from(&root).canonicalize().unwrap().join(&request.path[1..]);
-                    fs::write(path, file).unwrap();
-                    Response::with_text(status::OK, "ok")
+                    let path = match resolve_path(&root, &request.url) {
+                        Some(path) => path,
+                        None => return Response::with_text(status::FORBIDDEN, "forbidden"),
+                    };
+                    save_upload(request, Path::new(&root), &path, &options)
+                },
+                Method::Put => {
+                    let request = (*request).borrow();
+                    let path = match resolve_path(&root, &request.url) {
+                        Some(path) => path,
+                        None => return Response::with_text(status::FORBIDDEN, "forbidden"),
+                    };
+                    save_put(request, &path, &options)
                 },
                 Method::Delete => {
                     let request = (*request).borrow();
-                    let path = PathBuf::from(&root).canonicalize().unwrap().join(&request.path[1..]);
+                    let path = match resolve_path(&root, &request.url) {
+                        Some(path) => path,
+                        None => return Response::with_text(status::FORBIDDEN, "forbidden"),
+                    };
                     let file_type = fs::metadata(&path).unwrap().file_type();
                     if file_type.is_file() {
                         fs::remove_file(&path).unwrap();
@@ -252,5 +855,79 @@ uploadBtn.addEventListener(\"click\",function(){
             },
             None => Response::with_text(status::UNPROCESSABLE_ENTITY, "<h1>Unprocessable</h1>"),
         }
+    };
+    Box::new(Arc::new(move |request| -> Response {
+        let locale = Locale::from_request(&(*request).borrow());
+        let mut response = handle(request.clone(), locale);
+        locale.translate_response(&mut response);
+        let request = (*request).borrow();
+        if let Some(min_size) = compress_min_size {
+            compression::compress_response(&request, &mut response, min_size);
+        }
+        not_modified(&request, &mut response);
+        response
     }))
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, process};
+
+    use super::{serve_file, static_expect, StaticOptions};
+    use crate::infra::http::{
+        message::{HttpMessage, Request},
+        status,
+        url::Url,
+    };
+
+    fn request(method: &str, target: &str, length: usize) -> Request {
+        let mut request = Request {
+            method: String::from(method),
+            path: String::from(target),
+            url: Url::parse(target.as_bytes()).unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers: Default::default(),
+            body: Vec::new(),
+            body_reader: None,
+        };
+        request.set_header("Expect", "100-continue");
+        request.set_header("Content-Length", &length.to_string());
+        request
+    }
+
+    #[test]
+    fn expect_continue() {
+        let mut options = StaticOptions::new();
+        options.max_part_size = 100;
+        // tests run in the crate directory, so `.` holds `src`
+        let expect = static_expect(String::from("."), options);
+        assert!(expect(&request("POST", "/", 10)).is_none());
+        assert!(expect(&request("POST", "/src", 10)).is_none());
+        assert!(expect(&request("POST", "/src/new.txt", 10)).is_none());
+        assert!(expect(&request("PUT", "/new.txt", 10)).is_none());
+        let response = expect(&request("POST", "/missing/new.txt", 10)).unwrap();
+        assert_eq!(response.code, status::NOT_FOUND);
+        let response = expect(&request("PUT", "/new.txt", 1000)).unwrap();
+        assert_eq!(response.code, status::PAYLOAD_TOO_LARGE);
+        let response = expect(&request("DELETE", "/new.txt", 0)).unwrap();
+        assert_eq!(response.code, status::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn ranges_vary_like_whole_files() {
+        let path =
+            std::env::temp_dir().join(format!("https-server-app-vary-{}.txt", process::id()));
+        fs::write(&path, "hello world").unwrap();
+        let info = fs::metadata(&path).unwrap();
+        let mut request = request("GET", "/hello.txt", 0);
+        let response = serve_file(&request, &path, &info);
+        assert_eq!(response.code, status::OK);
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        request.set_header("Range", "bytes=0-4");
+        let response = serve_file(&request, &path, &info);
+        assert_eq!(response.code, status::PARTIAL_CONTENT);
+        assert_eq!(response.body, b"hello");
+        assert_eq!(response.get_header("Vary"), Some("Accept-Encoding"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,9 +1,22 @@
-use std::{cell::RefCell, rc::Rc};
+use std::{cell::RefCell, path::Path, process, rc::Rc};
 
 use self::state::AppState;
 
+pub use self::middleware::StaticOptions;
+
+mod archive;
+mod assets;
+mod compression;
+mod file_ops;
+mod i18n;
+mod listing;
 mod middleware;
+mod preview;
 mod state;
+mod templates;
+mod thumbnail;
+mod tus;
+#[cfg(feature = "gui")]
 mod ui;
 
 pub fn run(
@@ -12,9 +25,21 @@ pub fn run(
     bind: Option<&str>,
     root: Option<&str>,
     enable_gui: bool,
+    strict: bool,
+    options: StaticOptions,
 ) {
     let state = Rc::new(RefCell::new(AppState::new()));
+    state.borrow_mut().server.strict = strict;
+    state.borrow_mut().options = options;
+    #[cfg(not(feature = "gui"))]
+    let enable_gui = {
+        if enable_gui {
+            eprintln!("built without gui, running headless");
+        }
+        false
+    };
     if enable_gui {
+        #[cfg(feature = "gui")]
         ui::launch(state);
     } else {
         let mut state = state.borrow_mut();
@@ -24,9 +49,20 @@ pub fn run(
         state.server.bind_addr = bind.and_then(|s| Some(String::from(s)));
         let root_directory =
             String::from(state.root_directory.clone().unwrap_or(String::from(".")));
+        let options = state.options.clone();
+        if thumbnail::is_inside(&options.thumbnail_dir, Path::new(&root_directory)) {
+            eprintln!(
+                "thumbnail cache {} is inside the served root, choose another --thumbnail-dir",
+                options.thumbnail_dir.display()
+            );
+            process::exit(1);
+        }
         state
             .server
-            .launch(middleware::static_middleware(root_directory))
+            .launch(
+                middleware::static_middleware(root_directory.clone(), options.clone()),
+                Some(middleware::static_expect(root_directory, options)),
+            )
             .unwrap();
         loop {}
     }
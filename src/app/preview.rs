@@ -0,0 +1,164 @@
+use pulldown_cmark::{html, Options, Parser};
+use std::{fs, path::Path, sync::OnceLock};
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::highlighted_html_for_string,
+    parsing::{SyntaxDefinition, SyntaxSet},
+};
+
+use crate::infra::http::{
+    header,
+    message::{HttpMessage, Request},
+    mime,
+};
+
+/// larger files are sent as they are, highlighting them would hold up a worker for long
+const MAX_PREVIEW_SIZE: u64 = 1024 * 1024;
+
+const TOML_SYNTAX: &str = include_str!("syntaxes/TOML.sublime-syntax");
+
+const THEME: &str = "InspiredGitHub";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Preview {
+    Markdown,
+    Code,
+}
+
+impl Preview {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Preview::Markdown => "markdown",
+            Preview::Code => "code",
+        }
+    }
+}
+
+fn syntaxes() -> &'static SyntaxSet {
+    static SYNTAXES: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAXES.get_or_init(|| {
+        let mut builder = SyntaxSet::load_defaults_newlines().into_builder();
+        builder.add(SyntaxDefinition::load_from_str(TOML_SYNTAX, true, None).unwrap());
+        builder.build()
+    })
+}
+
+fn theme() -> &'static Theme {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    &THEME_SET.get_or_init(ThemeSet::load_defaults).themes[THEME]
+}
+
+/// Only a browser navigating to the file asks for a page, scripts and tools get the file
+pub fn wants_preview(request: &Request) -> bool {
+    if request.url.query.get("raw").is_some() {
+        return false;
+    }
+    request.get_header("Accept").is_some_and(|accept| {
+        accept.split(',').any(|item| {
+            header::parse_params(item)
+                .0
+                .eq_ignore_ascii_case("text/html")
+        })
+    })
+}
+
+/// How a file is previewed, if at all.
+/// Pages, scripts, stylesheets and data are loaded by other pages, so they are never previewed.
+pub fn kind(path: &Path) -> Option<Preview> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+    if matches!(extension.as_str(), "md" | "markdown") {
+        return Some(Preview::Markdown);
+    }
+    match mime::get_mime(&extension).as_deref() {
+        None | Some("text/plain") if extension != "txt" => {}
+        _ => return None,
+    }
+    syntaxes().find_syntax_by_extension(&extension)?;
+    Some(Preview::Code)
+}
+
+/// Markdown as HTML, with scripts, styles and event handlers removed
+pub fn render_markdown(text: &str) -> String {
+    let options = Options::ENABLE_TABLES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_FOOTNOTES;
+    let mut unsafe_html = String::new();
+    html::push_html(&mut unsafe_html, Parser::new_ext(text, options));
+    ammonia::Builder::default()
+        // the checkboxes of task lists
+        .add_tags(["input"])
+        .add_tag_attributes("input", ["type", "checked", "disabled"])
+        .clean(&unsafe_html)
+        .to_string()
+}
+
+/// Source code as highlighted HTML, `extension` picks the syntax
+pub fn highlight(text: &str, extension: &str) -> String {
+    let syntaxes = syntaxes();
+    let syntax = syntaxes
+        .find_syntax_by_extension(&extension.to_ascii_lowercase())
+        .unwrap_or_else(|| syntaxes.find_syntax_plain_text());
+    highlighted_html_for_string(text, syntaxes, syntax, theme())
+        .unwrap_or_else(|_| format!("<pre>{}</pre>", handlebars::html_escape(text)))
+}
+
+/// The preview of a file as an HTML fragment, `None` when it is sent as it is
+pub fn render(path: &Path, info: &fs::Metadata) -> Option<(Preview, String)> {
+    let kind = kind(path)?;
+    if info.len() > MAX_PREVIEW_SIZE {
+        return None;
+    }
+    let text = String::from_utf8(fs::read(path).ok()?).ok()?;
+    let extension = path.extension()?.to_str()?;
+    let content = match kind {
+        Preview::Markdown => render_markdown(&text),
+        Preview::Code => highlight(&text, extension),
+    };
+    Some((kind, content))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{highlight, kind, render_markdown, Preview};
+
+    #[test]
+    fn preview_kinds() {
+        assert_eq!(kind(Path::new("README.md")), Some(Preview::Markdown));
+        assert_eq!(kind(Path::new("main.RS")), Some(Preview::Code));
+        assert_eq!(kind(Path::new("Cargo.toml")), Some(Preview::Code));
+        // browsers load these themselves
+        assert_eq!(kind(Path::new("index.html")), None);
+        assert_eq!(kind(Path::new("app.js")), None);
+        assert_eq!(kind(Path::new("data.json")), None);
+        assert_eq!(kind(Path::new("robots.txt")), None);
+        assert_eq!(kind(Path::new("photo.png")), None);
+        assert_eq!(kind(Path::new("Makefile")), None);
+    }
+
+    #[test]
+    fn sanitize_markdown() {
+        let html = render_markdown(
+            "# Title\n\n<script>alert(1)</script>\n\n[link](javascript:alert(1)) <img src=x onerror=alert(1)>\n\n- [x] done\n",
+        );
+        assert!(html.contains("<h1>Title</h1>"));
+        assert!(!html.contains("<script"));
+        assert!(!html.contains("javascript:"));
+        assert!(!html.contains("onerror"));
+        assert!(html.contains("type=\"checkbox\""));
+    }
+
+    #[test]
+    fn highlight_code() {
+        let html = highlight("[package]\nname = \"demo\" # comment\n", "toml");
+        assert!(html.starts_with("<pre"));
+        assert!(html.contains("<span"));
+        assert!(html.contains("demo"));
+        assert!(!html.contains("<script"));
+
+        let html = highlight("<script>", "unknown-extension");
+        assert!(html.contains("&lt;script&gt;"));
+    }
+}
@@ -1,9 +1,16 @@
+#[cfg(feature = "gui")]
+use super::i18n::Locale;
+use super::middleware::StaticOptions;
 use crate::infra::https::HttpsServer;
 
 #[derive(Debug)]
 pub struct AppState {
     pub server: HttpsServer,
     pub root_directory: Option<String>,
+    pub options: StaticOptions,
+    /// language of the GUI, pages follow the Accept-Language of each request
+    #[cfg(feature = "gui")]
+    pub locale: Locale,
 }
 
 impl AppState {
@@ -11,6 +18,9 @@ impl AppState {
         Self {
             server: HttpsServer::new(),
             root_directory: None,
+            options: StaticOptions::new(),
+            #[cfg(feature = "gui")]
+            locale: Locale::from_env(),
         }
     }
 }
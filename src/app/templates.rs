@@ -0,0 +1,172 @@
+use handlebars::{Context, Handlebars, Helper, HelperDef, RenderContext, RenderError, ScopedJson};
+use serde_json::Value;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use super::{
+    i18n::Locale,
+    middleware::{is_hidden, StaticOptions},
+};
+
+const BUILTIN: [(&str, &str); 5] = [
+    ("layout", include_str!("templates/layout.hbs")),
+    ("index", include_str!("templates/index.hbs")),
+    ("not_found", include_str!("templates/not_found.hbs")),
+    ("unknown", include_str!("templates/unknown.hbs")),
+    ("preview", include_str!("templates/preview.hbs")),
+];
+
+/// `{{t "Upload"}}` translates a message into the language of the page, `{name}` placeholders
+/// are filled from the hash like `{{t "Index {path}" path=path}}`, `{{{tjs "..."}}}` gives a JavaScript string
+struct Translate {
+    javascript: bool,
+}
+
+impl HelperDef for Translate {
+    fn call_inner<'reg: 'rc, 'rc>(
+        &self,
+        helper: &Helper<'reg, 'rc>,
+        _: &'reg Handlebars<'reg>,
+        context: &'rc Context,
+        _: &mut RenderContext<'reg, 'rc>,
+    ) -> Result<ScopedJson<'reg, 'rc>, RenderError> {
+        let message = helper
+            .param(0)
+            .and_then(|param| param.value().as_str())
+            .ok_or_else(|| RenderError::new("t expects a message"))?;
+        let locale = context.data()["lang"]
+            .as_str()
+            .and_then(Locale::from_tag)
+            .unwrap_or(Locale::En);
+        let mut text = String::from(locale.text(message));
+        for (name, value) in helper.hash() {
+            let value = match value.value() {
+                Value::String(value) => value.clone(),
+                value => value.to_string(),
+            };
+            text = text.replace(&format!("{{{}}}", name), &value);
+        }
+        if self.javascript {
+            // a closing script tag in a string would end the script
+            text = Value::String(text).to_string().replace('<', "\\u003c");
+        }
+        Ok(ScopedJson::Derived(Value::String(text)))
+    }
+}
+
+/// The HTML templates, `name.hbs` files in the templates directory replace or add to the built-ins
+///
+/// Example
+/// ```ignore
+/// templates/layout.hbs    replaces the page layout
+/// templates/footer.hbs    a new partial, used as {{> footer}}
+/// ```
+pub struct Templates {
+    builtin: Handlebars<'static>,
+    /// the built-ins with the overrides registered on top
+    loaded: Handlebars<'static>,
+    dir: Option<PathBuf>,
+    hot_reload: bool,
+}
+
+impl Templates {
+    pub fn new(options: &StaticOptions) -> Self {
+        let mut builtin = Handlebars::new();
+        builtin.register_helper("t", Box::new(Translate { javascript: false }));
+        builtin.register_helper("tjs", Box::new(Translate { javascript: true }));
+        for (name, source) in BUILTIN {
+            builtin.register_template_string(name, source).unwrap();
+        }
+        let loaded = load(&builtin, options.templates_dir.as_deref());
+        Self {
+            builtin,
+            loaded,
+            dir: options.templates_dir.clone(),
+            hot_reload: options.hot_reload,
+        }
+    }
+    /// Renders `name` in `locale`, falling back to the built-in template when an override fails
+    pub fn render(&self, name: &str, data: &Value, locale: Locale) -> String {
+        let mut data = data.clone();
+        data["lang"] = Value::String(String::from(locale.tag()));
+        let data = &data;
+        let rendered = if self.hot_reload {
+            load(&self.builtin, self.dir.as_deref()).render(name, data)
+        } else {
+            self.loaded.render(name, data)
+        };
+        rendered.unwrap_or_else(|err| {
+            eprintln!("{:?}", err);
+            self.builtin.render(name, data).unwrap()
+        })
+    }
+}
+
+/// Registers the templates of `dir` over the built-ins, a template that does not parse is skipped
+fn load(builtin: &Handlebars<'static>, dir: Option<&Path>) -> Handlebars<'static> {
+    let mut registry = builtin.clone();
+    let files = match dir.map(fs::read_dir) {
+        Some(Ok(files)) => files,
+        Some(Err(err)) => {
+            eprintln!("{:?}", err);
+            return registry;
+        }
+        None => return registry,
+    };
+    for file in files.flatten() {
+        let path = file.path();
+        let name = match path.file_stem().and_then(|name| name.to_str()) {
+            Some(name) if !is_hidden(name) => name,
+            _ => continue,
+        };
+        if path.extension().and_then(|extension| extension.to_str()) != Some("hbs") {
+            continue;
+        }
+        if let Err(err) = registry.register_template_file(name, &path) {
+            eprintln!("{:?}", err);
+        }
+    }
+    registry
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use std::{fs, process};
+
+    use super::Templates;
+    use crate::app::{i18n::Locale, StaticOptions};
+
+    #[test]
+    fn override_templates() {
+        let dir = std::env::temp_dir().join(format!("templates-{}", process::id()));
+        fs::remove_dir_all(&dir).ok();
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("not_found.hbs"), "missing {{path}} {{> footer}}").unwrap();
+        fs::write(dir.join("footer.hbs"), "(footer)").unwrap();
+        fs::write(dir.join("unknown.hbs"), "{{#if}").unwrap();
+
+        let mut options = StaticOptions::new();
+        options.templates_dir = Some(dir.clone());
+        let templates = Templates::new(&options);
+        let data = json!({ "path": "/a" });
+        assert_eq!(templates.render("not_found", &data, Locale::En), "missing /a (footer)");
+        // a broken override leaves the built-in in place
+        assert!(templates
+            .render("unknown", &data, Locale::En)
+            .contains("Unknown Forbidden /a"));
+
+        options.hot_reload = true;
+        let templates = Templates::new(&options);
+        fs::write(dir.join("footer.hbs"), "(changed)").unwrap();
+        assert_eq!(templates.render("not_found", &data, Locale::En), "missing /a (changed)");
+        // a template that parses but fails to render falls back as well
+        fs::write(dir.join("not_found.hbs"), "{{missing_helper path}}").unwrap();
+        assert!(templates
+            .render("not_found", &data, Locale::En)
+            .contains("Not Found /a"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
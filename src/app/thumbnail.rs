@@ -0,0 +1,196 @@
+use image::{ImageFormat, ImageReader};
+use std::{
+    collections::hash_map::DefaultHasher,
+    fs,
+    hash::{Hash, Hasher},
+    io::{BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+
+use super::middleware::{create_temp_file, serve_file, StaticOptions};
+use crate::infra::http::{
+    message::{Request, Response},
+    mime, status,
+};
+
+/// longest side of a thumbnail in pixels, twice the size shown for sharp high-density screens
+const THUMBNAIL_SIZE: u32 = 320;
+
+/// image types with a thumbnail, the ones `image` is built to decode
+const IMAGE_TYPES: [&str; 5] = [
+    "image/jpeg",
+    "image/png",
+    "image/gif",
+    "image/webp",
+    "image/bmp",
+];
+
+/// Whether a file name is one of the image types with a thumbnail
+pub fn is_image(name: &str) -> bool {
+    let extension = Path::new(name)
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or("");
+    mime::get_mime(extension).is_some_and(|mime_type| IMAGE_TYPES.contains(&mime_type.as_str()))
+}
+
+/// Whether `dir` is `root` or below it, so a cache there would be served like any other file
+pub fn is_inside(dir: &Path, root: &Path) -> bool {
+    // the cache directory is created on the first thumbnail, so the closest existing ancestor stands in
+    let dir = dir.ancestors().find_map(|ancestor| {
+        fs::canonicalize(ancestor)
+            .ok()
+            .map(|found| found.join(dir.strip_prefix(ancestor).unwrap()))
+    });
+    match (dir, fs::canonicalize(root)) {
+        (Some(dir), Ok(root)) => dir.starts_with(root),
+        _ => false,
+    }
+}
+
+/// Where the thumbnail of a file is cached, named after the file and its version,
+/// so a changed file gets a new thumbnail. Photos stay JPEG, other types keep transparency as PNG.
+fn cache_path(cache_dir: &Path, path: &Path, info: &fs::Metadata) -> PathBuf {
+    let mut hasher = DefaultHasher::new();
+    path.hash(&mut hasher);
+    info.len().hash(&mut hasher);
+    info.modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map(|modified| modified.as_nanos())
+        .hash(&mut hasher);
+    let photo = mime::get_mime(
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .unwrap_or(""),
+    )
+    .as_deref()
+        == Some("image/jpeg");
+    let extension = if photo { "jpg" } else { "png" };
+    cache_dir.join(format!(
+        "{:016x}-{}.{}",
+        hasher.finish(),
+        THUMBNAIL_SIZE,
+        extension
+    ))
+}
+
+/// Decodes `path` and writes its scaled down copy to `target`, which only appears once complete
+fn generate(path: &Path, target: &Path) -> Result<(), String> {
+    let image = ImageReader::open(path)
+        .and_then(|reader| reader.with_guessed_format())
+        .map_err(|err| err.to_string())?
+        .decode()
+        .map_err(|err| err.to_string())?;
+    let thumbnail = image.thumbnail(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let thumbnail = match target.extension().and_then(|extension| extension.to_str()) {
+        // JPEG has no alpha channel
+        Some("jpg") => image::DynamicImage::ImageRgb8(thumbnail.to_rgb8()),
+        _ => thumbnail,
+    };
+    let format = ImageFormat::from_path(target).map_err(|err| err.to_string())?;
+    if let Some(dir) = target.parent() {
+        fs::create_dir_all(dir).map_err(|err| err.to_string())?;
+    }
+    let (temp_path, file) = create_temp_file(target).map_err(|err| err.to_string())?;
+    let mut writer = BufWriter::new(file);
+    let written = thumbnail
+        .write_to(&mut writer, format)
+        .map_err(|err| err.to_string())
+        .and_then(|_| writer.flush().map_err(|err| err.to_string()));
+    drop(writer);
+    if let Err(err) =
+        written.and_then(|_| fs::rename(&temp_path, target).map_err(|err| err.to_string()))
+    {
+        fs::remove_file(&temp_path).ok();
+        return Err(err);
+    }
+    Ok(())
+}
+
+/// The cached thumbnail of an image, made on the first request for it
+pub fn thumbnail(path: &Path, info: &fs::Metadata, cache_dir: &Path) -> Result<PathBuf, String> {
+    let cached = cache_path(cache_dir, path, info);
+    if !cached.is_file() {
+        generate(path, &cached)?;
+    }
+    Ok(cached)
+}
+
+/// Answers `GET /photos/cat.jpg?thumbnail=1`
+pub fn handle(
+    request: &Request,
+    path: &Path,
+    info: &fs::Metadata,
+    options: &StaticOptions,
+) -> Response {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    if !is_image(&name) {
+        return Response::with_text(status::NOT_FOUND, "no thumbnail for this type");
+    }
+    let cached = match thumbnail(path, info, &options.thumbnail_dir) {
+        Ok(cached) => cached,
+        Err(err) => {
+            eprintln!("thumbnail of {} failed: {}", path.display(), err);
+            return Response::with_text(status::UNSUPPORTED_MEDIA_TYPE, "cannot read this image");
+        }
+    };
+    match fs::metadata(&cached) {
+        Ok(cached_info) => serve_file(request, &cached, &cached_info),
+        Err(err) => Response::with_text(status::INTERNAL_SERVER_ERROR, &err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use image::{GenericImageView, Rgba, RgbaImage};
+    use std::{fs, process};
+
+    use super::{is_image, is_inside, thumbnail, THUMBNAIL_SIZE};
+
+    #[test]
+    fn image_names() {
+        assert!(is_image("cat.JPG"));
+        assert!(is_image("diagram.png"));
+        assert!(!is_image("notes.txt"));
+        assert!(!is_image("vector.svg"));
+    }
+
+    #[test]
+    fn cached_thumbnails() {
+        let dir =
+            std::env::temp_dir().join(format!("https-server-app-thumbnails-{}", process::id()));
+        let cache_dir = dir.join("cache");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("wide.png");
+        RgbaImage::from_pixel(800, 400, Rgba([200, 10, 10, 128]))
+            .save(&path)
+            .unwrap();
+        let info = fs::metadata(&path).unwrap();
+
+        let cached = thumbnail(&path, &info, &cache_dir).unwrap();
+        assert!(cached.starts_with(&cache_dir));
+        let image = image::open(&cached).unwrap();
+        assert_eq!(image.dimensions(), (THUMBNAIL_SIZE, THUMBNAIL_SIZE / 2));
+        assert!(image.color().has_alpha());
+
+        // the second request reuses the cached file
+        let modified = fs::metadata(&cached).unwrap().modified().unwrap();
+        assert_eq!(thumbnail(&path, &info, &cache_dir).unwrap(), cached);
+        assert_eq!(fs::metadata(&cached).unwrap().modified().unwrap(), modified);
+
+        fs::write(dir.join("broken.jpg"), "not an image").unwrap();
+        let broken = dir.join("broken.jpg");
+        assert!(thumbnail(&broken, &fs::metadata(&broken).unwrap(), &cache_dir).is_err());
+
+        // the cache directory may not exist yet
+        assert!(is_inside(&dir.join("new/cache"), &dir));
+        assert!(is_inside(&dir.join("cache/../cache"), &dir));
+        assert!(!is_inside(&cache_dir, &dir.join("root")));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
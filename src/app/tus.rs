@@ -0,0 +1,642 @@
+use serde_json::json;
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    process,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Mutex,
+    },
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use super::middleware::{
+    create_temp_file, is_reserved, upload_directory, upload_file_name, StaticOptions,
+};
+use crate::infra::http::{
+    date,
+    message::{HttpMessage, Request, Response},
+    method::{self, Method},
+    status,
+    url::Url,
+};
+
+/// uploads live under `/.tus/`, see https://tus.io/protocols/resumable-upload
+pub const TUS_PREFIX: &[u8] = b".tus";
+const TUS_VERSION: &str = "1.0.0";
+const TUS_EXTENSIONS: &str = "creation,expiration,termination";
+
+/// uploads a PATCH is currently appending to, a second PATCH must not interleave
+static ACTIVE: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+struct ActiveGuard(String);
+
+impl ActiveGuard {
+    fn acquire(id: &str) -> Option<Self> {
+        let mut active = ACTIVE.lock().unwrap_or_else(|err| err.into_inner());
+        if active.iter().any(|item| item == id) {
+            return None;
+        }
+        active.push(String::from(id));
+        Some(Self(String::from(id)))
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        let mut active = ACTIVE.lock().unwrap_or_else(|err| err.into_inner());
+        active.retain(|item| *item != self.0);
+    }
+}
+
+pub fn is_tus(url: &Url) -> bool {
+    url.segments.first().map(|segment| segment.as_slice()) == Some(TUS_PREFIX)
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs()
+}
+
+fn new_id() -> String {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_nanos();
+    format!(
+        "{:x}{:08x}{:04x}",
+        nanos,
+        process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed) & 0xffff
+    )
+}
+
+fn is_valid_id(id: &[u8]) -> bool {
+    !id.is_empty() && id.len() <= 64 && id.iter().all(|byte| byte.is_ascii_hexdigit())
+}
+
+fn decode_base64(value: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let value = value.trim_end_matches('=');
+    let mut bytes = Vec::with_capacity(value.len() * 3 / 4);
+    let mut buffer: u32 = 0;
+    let mut bits = 0;
+    for byte in value.bytes() {
+        let index = ALPHABET.iter().position(|c| *c == byte)? as u32;
+        buffer = buffer << 6 | index;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+            buffer &= (1 << bits) - 1;
+        }
+    }
+    Some(bytes)
+}
+
+/// Upload-Metadata is a comma separated list of keys with base64 encoded values
+fn parse_metadata(value: &str) -> Option<Vec<(String, String)>> {
+    let mut pairs = Vec::new();
+    for pair in value.split(',') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+        let (key, value) = pair.split_once(' ').unwrap_or((pair, ""));
+        let value = String::from_utf8(decode_base64(value.trim())?).ok()?;
+        pairs.push((String::from(key), value));
+    }
+    Some(pairs)
+}
+
+fn tus_response(code: status::Status) -> Response {
+    let mut response = Response::with_text(code, "");
+    response.set_header("Tus-Resumable", TUS_VERSION);
+    response
+}
+
+fn tus_error(code: status::Status, text: &str) -> Response {
+    let mut response = Response::with_text(code, text);
+    response.set_header("Tus-Resumable", TUS_VERSION);
+    response
+}
+
+/// A partial upload in the staging area, its data and its description sit side by side
+struct Upload {
+    id: String,
+    length: u64,
+    dir: String,
+    filename: String,
+    overwrite: bool,
+    /// create `dir` when missing
+    parents: bool,
+    metadata: String,
+    expires: u64,
+}
+
+impl Upload {
+    fn data_path(staging: &Path, id: &str) -> PathBuf {
+        staging.join(format!("{}.bin", id))
+    }
+    fn info_path(staging: &Path, id: &str) -> PathBuf {
+        staging.join(format!("{}.info", id))
+    }
+    fn load(staging: &Path, id: &str) -> Option<Self> {
+        let info = fs::read(Upload::info_path(staging, id)).ok()?;
+        let info: serde_json::Value = serde_json::from_slice(&info).ok()?;
+        Some(Self {
+            id: String::from(id),
+            length: info["length"].as_u64()?,
+            dir: String::from(info["dir"].as_str()?),
+            filename: String::from(info["filename"].as_str()?),
+            overwrite: info["overwrite"].as_bool()?,
+            parents: info["parents"].as_bool().unwrap_or(false),
+            metadata: String::from(info["metadata"].as_str()?),
+            expires: info["expires"].as_u64()?,
+        })
+    }
+    fn save(&self, staging: &Path) -> io::Result<()> {
+        let info = json!({
+            "length": self.length,
+            "dir": self.dir,
+            "filename": self.filename,
+            "overwrite": self.overwrite,
+            "parents": self.parents,
+            "metadata": self.metadata,
+            "expires": self.expires,
+        });
+        fs::write(Upload::info_path(staging, &self.id), info.to_string())
+    }
+    fn remove(&self, staging: &Path) {
+        fs::remove_file(Upload::data_path(staging, &self.id)).ok();
+        fs::remove_file(Upload::info_path(staging, &self.id)).ok();
+    }
+    fn offset(&self, staging: &Path) -> u64 {
+        fs::metadata(Upload::data_path(staging, &self.id))
+            .map(|info| info.len())
+            .unwrap_or(0)
+    }
+    fn expires_header(&self) -> String {
+        date::format(UNIX_EPOCH + Duration::from_secs(self.expires))
+    }
+    fn target(&self, root: &str) -> Result<PathBuf, Response> {
+        let root = Path::new(root);
+        let directory = upload_directory(root, root, self.dir.as_bytes(), self.parents)
+            .map_err(|(code, error)| tus_error(code, &error))?;
+        let filename = upload_file_name(&self.filename)
+            .ok_or_else(|| tus_error(status::BAD_REQUEST, "invalid filename"))?;
+        let target = directory.join(filename);
+        if is_reserved(root, &target) {
+            return Err(tus_error(status::FORBIDDEN, "reserved name"));
+        }
+        if !self.overwrite && target.exists() {
+            return Err(tus_error(status::CONFLICT, "file already exists"));
+        }
+        Ok(target)
+    }
+    /// Moves the completed data onto its target, copying when the staging area is on another filesystem
+    fn finish(&self, root: &str, staging: &Path) -> Result<(), Response> {
+        let target = self.target(root)?;
+        let data_path = Upload::data_path(staging, &self.id);
+        let internal_error =
+            |err: io::Error| tus_error(status::INTERNAL_SERVER_ERROR, &err.to_string());
+        if fs::rename(&data_path, &target).is_err() {
+            let (temp_path, mut file) = create_temp_file(&target).map_err(internal_error)?;
+            let copied = File::open(&data_path).and_then(|mut data| io::copy(&mut data, &mut file));
+            drop(file);
+            if let Err(err) = copied.and_then(|_| fs::rename(&temp_path, &target)) {
+                fs::remove_file(&temp_path).ok();
+                return Err(internal_error(err));
+            }
+        }
+        self.remove(staging);
+        Ok(())
+    }
+}
+
+/// Drops uploads nobody resumed before they expired
+fn remove_expired(staging: &Path) {
+    let entries = match fs::read_dir(staging) {
+        Ok(entries) => entries,
+        Err(_) => return,
+    };
+    let now = now();
+    for entry in entries.flatten() {
+        let name = entry.file_name();
+        let id = match name.to_str().and_then(|name| name.strip_suffix(".info")) {
+            Some(id) => id,
+            None => continue,
+        };
+        match Upload::load(staging, id) {
+            Some(upload) if upload.expires > now => {}
+            Some(upload) => upload.remove(staging),
+            None => {
+                fs::remove_file(Upload::info_path(staging, id)).ok();
+                fs::remove_file(Upload::data_path(staging, id)).ok();
+            }
+        }
+    }
+}
+
+fn create(request: &Request, root: &str, options: &StaticOptions) -> Response {
+    if request.get_header("Upload-Defer-Length").is_some() {
+        return tus_error(status::BAD_REQUEST, "deferred length is not supported");
+    }
+    let length = match request
+        .get_header("Upload-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+    {
+        Some(length) => length,
+        None => return tus_error(status::BAD_REQUEST, "invalid Upload-Length"),
+    };
+    if length > options.max_part_size {
+        return tus_error(status::PAYLOAD_TOO_LARGE, "upload too large");
+    }
+    let metadata = request.get_header("Upload-Metadata").unwrap_or("");
+    let pairs = match parse_metadata(metadata) {
+        Some(pairs) => pairs,
+        None => return tus_error(status::BAD_REQUEST, "invalid Upload-Metadata"),
+    };
+    let get = |key: &str| {
+        pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, value)| value.as_str())
+    };
+    let upload = Upload {
+        id: new_id(),
+        length,
+        dir: String::from(get("dir").unwrap_or("")),
+        filename: match get("filename") {
+            Some(filename) => String::from(filename),
+            None => return tus_error(status::BAD_REQUEST, "missing filename metadata"),
+        },
+        overwrite: !matches!(get("overwrite"), Some("0") | Some("false")),
+        parents: matches!(get("parents"), Some("1") | Some("true")),
+        metadata: String::from(metadata),
+        expires: now() + options.upload_expiration.as_secs(),
+    };
+    // refuse early instead of after the whole upload went through
+    if let Err(response) = upload.target(root) {
+        return response;
+    }
+
+    let staging = &options.staging_dir;
+    let created = fs::create_dir_all(staging)
+        .and_then(|_| {
+            OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(Upload::data_path(staging, &upload.id))
+        })
+        .and_then(|_| upload.save(staging));
+    if let Err(err) = created {
+        upload.remove(staging);
+        return tus_error(status::INTERNAL_SERVER_ERROR, &err.to_string());
+    }
+    if length == 0 {
+        if let Err(response) = upload.finish(root, staging) {
+            upload.remove(staging);
+            return response;
+        }
+    }
+    let mut response = tus_response(status::CREATED);
+    response.set_header("Location", &format!("/.tus/{}", upload.id));
+    response.set_header("Upload-Expires", &upload.expires_header());
+    response
+}
+
+fn append(request: &Request, root: &str, options: &StaticOptions, id: &str) -> Response {
+    let content_type = request.get_header("Content-Type").unwrap_or("");
+    if !content_type.eq_ignore_ascii_case("application/offset+octet-stream") {
+        return tus_error(
+            status::UNSUPPORTED_MEDIA_TYPE,
+            "expected application/offset+octet-stream",
+        );
+    }
+    let offset = match request
+        .get_header("Upload-Offset")
+        .and_then(|offset| offset.parse::<u64>().ok())
+    {
+        Some(offset) => offset,
+        None => return tus_error(status::BAD_REQUEST, "invalid Upload-Offset"),
+    };
+    let _guard = match ActiveGuard::acquire(id) {
+        Some(guard) => guard,
+        None => return tus_error(status::CONFLICT, "upload in progress"),
+    };
+    let staging = &options.staging_dir;
+    let mut upload = match Upload::load(staging, id) {
+        Some(upload) => upload,
+        None => return tus_error(status::NOT_FOUND, "upload not found"),
+    };
+    if offset != upload.offset(staging) {
+        return tus_error(status::CONFLICT, "Upload-Offset mismatch");
+    }
+    let content_length = request
+        .get_header("Content-Length")
+        .and_then(|length| length.parse::<u64>().ok())
+        .unwrap_or(0);
+    if offset.saturating_add(content_length) > upload.length {
+        return tus_error(status::PAYLOAD_TOO_LARGE, "chunk exceeds Upload-Length");
+    }
+
+    if let Some(body) = request.body_reader.clone() {
+        let file = OpenOptions::new()
+            .append(true)
+            .open(Upload::data_path(staging, id));
+        let mut file = match file {
+            Ok(file) => file,
+            Err(err) => return tus_error(status::INTERNAL_SERVER_ERROR, &err.to_string()),
+        };
+        // whatever arrived before a dropped connection stays, the client resumes from there
+        if let Err(err) = io::copy(&mut body.take(upload.length - offset), &mut file) {
+            return tus_error(status::BAD_REQUEST, &err.to_string());
+        }
+    }
+    let offset = upload.offset(staging);
+    upload.expires = now() + options.upload_expiration.as_secs();
+    if offset == upload.length {
+        if let Err(response) = upload.finish(root, staging) {
+            return response;
+        }
+    } else if let Err(err) = upload.save(staging) {
+        return tus_error(status::INTERNAL_SERVER_ERROR, &err.to_string());
+    }
+    let mut response = tus_response(status::NO_CONTENT);
+    response.set_header("Upload-Offset", &offset.to_string());
+    response.set_header("Upload-Expires", &upload.expires_header());
+    response
+}
+
+fn status_of(root: &str, options: &StaticOptions, id: &str) -> Response {
+    let staging = &options.staging_dir;
+    let upload = match Upload::load(staging, id) {
+        Some(upload) => upload,
+        None => return tus_error(status::NOT_FOUND, "upload not found"),
+    };
+    let offset = upload.offset(staging);
+    // all data arrived but the file could not be put in place, a client checking
+    // the offset would take it as done, so the last step is tried again
+    if offset == upload.length {
+        let _guard = match ActiveGuard::acquire(id) {
+            Some(guard) => guard,
+            None => return tus_error(status::CONFLICT, "upload in progress"),
+        };
+        if let Err(response) = upload.finish(root, staging) {
+            return response;
+        }
+    }
+    let mut response = tus_response(status::OK);
+    response.set_header("Upload-Offset", &offset.to_string());
+    response.set_header("Upload-Length", &upload.length.to_string());
+    if !upload.metadata.is_empty() {
+        response.set_header("Upload-Metadata", &upload.metadata);
+    }
+    response.set_header("Upload-Expires", &upload.expires_header());
+    response.set_header("Cache-Control", "no-store");
+    response
+}
+
+fn terminate(options: &StaticOptions, id: &str) -> Response {
+    let _guard = match ActiveGuard::acquire(id) {
+        Some(guard) => guard,
+        None => return tus_error(status::CONFLICT, "upload in progress"),
+    };
+    let staging = &options.staging_dir;
+    match Upload::load(staging, id) {
+        Some(upload) => {
+            upload.remove(staging);
+            tus_response(status::NO_CONTENT)
+        }
+        None => tus_error(status::NOT_FOUND, "upload not found"),
+    }
+}
+
+/// Serves the tus endpoints, finished uploads land in `root` like regular ones
+pub fn handle(request: &Request, root: &str, options: &StaticOptions) -> Response {
+    let method = method::get_methods(request.method.as_str());
+    if method == Some(Method::Options) {
+        let mut response = tus_response(status::NO_CONTENT);
+        response.set_header("Tus-Version", TUS_VERSION);
+        response.set_header("Tus-Extension", TUS_EXTENSIONS);
+        if options.max_part_size != u64::MAX {
+            response.set_header("Tus-Max-Size", &options.max_part_size.to_string());
+        }
+        return response;
+    }
+    if request.get_header("Tus-Resumable") != Some(TUS_VERSION) {
+        let mut response = tus_error(status::PRECONDITION_FAILED, "unsupported tus version");
+        response.set_header("Tus-Version", TUS_VERSION);
+        return response;
+    }
+    remove_expired(&options.staging_dir);
+
+    let segments: Vec<&[u8]> = request.url.segments[1..]
+        .iter()
+        .map(|segment| segment.as_slice())
+        .filter(|segment| !segment.is_empty())
+        .collect();
+    match (method, segments.as_slice()) {
+        (Some(Method::Post), []) => create(request, root, options),
+        (_, []) => tus_error(status::METHOD_NOT_ALLOWED, "method not allowed"),
+        (method, [id]) if is_valid_id(id) => {
+            let id = String::from_utf8_lossy(id);
+            match method {
+                Some(Method::Head) => status_of(root, options, &id),
+                Some(Method::Patch) => append(request, root, options, &id),
+                Some(Method::Delete) => terminate(options, &id),
+                _ => tus_error(status::METHOD_NOT_ALLOWED, "method not allowed"),
+            }
+        }
+        _ => tus_error(status::NOT_FOUND, "upload not found"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        fs,
+        io::Cursor,
+        path::{Path, PathBuf},
+        process,
+        rc::Rc,
+        time::Duration,
+    };
+
+    use super::{decode_base64, handle, parse_metadata};
+    use crate::app::middleware::StaticOptions;
+    use crate::infra::http::{
+        message::{BodyReader, HttpMessage, Request, Response},
+        status,
+        url::Url,
+    };
+
+    fn setup(name: &str) -> (PathBuf, String, StaticOptions) {
+        let dir =
+            std::env::temp_dir().join(format!("https-server-app-tus-{}-{}", name, process::id()));
+        let root = dir.join("root");
+        fs::create_dir_all(&root).unwrap();
+        let mut options = StaticOptions::new();
+        options.staging_dir = dir.join("staging");
+        (dir, root.to_string_lossy().into_owned(), options)
+    }
+
+    fn request(method: &str, path: &str, headers: &[(&str, &str)], body: &[u8]) -> Request {
+        let mut request = Request {
+            method: String::from(method),
+            path: String::from(path),
+            url: Url::parse(path.as_bytes()).unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers: Default::default(),
+            body: Vec::new(),
+            body_reader: None,
+        };
+        request.set_header("Tus-Resumable", "1.0.0");
+        for (key, value) in headers {
+            request.set_header(key, value);
+        }
+        if !body.is_empty() {
+            request.set_header("Content-Length", &body.len().to_string());
+            let connection = Rc::new(RefCell::new(Cursor::new(body.to_vec())));
+            request.body_reader = Some(BodyReader::new(connection, body.len() as u64));
+        }
+        request
+    }
+
+    fn create(root: &str, options: &StaticOptions, length: &str, metadata: &str) -> Response {
+        let headers = [("Upload-Length", length), ("Upload-Metadata", metadata)];
+        handle(&request("POST", "/.tus/", &headers, b""), root, options)
+    }
+
+    fn patch(
+        root: &str,
+        options: &StaticOptions,
+        location: &str,
+        offset: &str,
+        body: &[u8],
+    ) -> Response {
+        let headers = [
+            ("Content-Type", "application/offset+octet-stream"),
+            ("Upload-Offset", offset),
+        ];
+        handle(&request("PATCH", location, &headers, body), root, options)
+    }
+
+    fn head(root: &str, options: &StaticOptions, location: &str) -> Response {
+        handle(&request("HEAD", location, &[], b""), root, options)
+    }
+
+    #[test]
+    fn upload_in_chunks() {
+        let (dir, root, options) = setup("chunks");
+        let response = create(&root, &options, "11", "filename aGVsbG8udHh0");
+        assert_eq!(response.code, status::CREATED);
+        let location = String::from(response.get_header("Location").unwrap());
+        assert!(location.starts_with("/.tus/"));
+        let response = head(&root, &options, &location);
+        assert_eq!(response.get_header("Upload-Offset"), Some("0"));
+        assert_eq!(response.get_header("Upload-Length"), Some("11"));
+
+        let headers = [
+            ("Content-Type", "application/octet-stream"),
+            ("Upload-Offset", "0"),
+        ];
+        let response = handle(
+            &request("PATCH", &location, &headers, b"hello "),
+            &root,
+            &options,
+        );
+        assert_eq!(response.code, status::UNSUPPORTED_MEDIA_TYPE);
+
+        let response = patch(&root, &options, &location, "0", b"hello ");
+        assert_eq!(response.code, status::NO_CONTENT);
+        assert_eq!(response.get_header("Upload-Offset"), Some("6"));
+        assert_eq!(
+            head(&root, &options, &location).get_header("Upload-Offset"),
+            Some("6")
+        );
+        let response = patch(&root, &options, &location, "3", b"lo world");
+        assert_eq!(response.code, status::CONFLICT);
+
+        let response = patch(&root, &options, &location, "6", b"world");
+        assert_eq!(response.code, status::NO_CONTENT);
+        assert_eq!(response.get_header("Upload-Offset"), Some("11"));
+        assert_eq!(
+            fs::read(Path::new(&root).join("hello.txt")).unwrap(),
+            b"hello world"
+        );
+        assert_eq!(head(&root, &options, &location).code, status::NOT_FOUND);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn terminate_and_expire() {
+        let (dir, root, mut options) = setup("terminate");
+        let response = create(&root, &options, "5", "filename aGVsbG8udHh0");
+        let location = String::from(response.get_header("Location").unwrap());
+        let response = handle(&request("DELETE", &location, &[], b""), &root, &options);
+        assert_eq!(response.code, status::NO_CONTENT);
+        assert_eq!(head(&root, &options, &location).code, status::NOT_FOUND);
+
+        options.upload_expiration = Duration::ZERO;
+        let response = create(&root, &options, "5", "filename aGVsbG8udHh0");
+        let location = String::from(response.get_header("Location").unwrap());
+        // any later request sweeps the staging area
+        assert_eq!(head(&root, &options, &location).code, status::NOT_FOUND);
+        assert_eq!(fs::read_dir(&options.staging_dir).unwrap().count(), 0);
+        assert!(!Path::new(&root).join("hello.txt").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn retry_failed_finish() {
+        let (dir, root, options) = setup("finish");
+        let target = Path::new(&root).join("taken.txt");
+        let response = create(&root, &options, "4", "filename dGFrZW4udHh0,overwrite MA==");
+        let location = String::from(response.get_header("Location").unwrap());
+        fs::write(&target, "old").unwrap();
+        let response = patch(&root, &options, &location, "0", b"data");
+        assert_eq!(response.code, status::CONFLICT);
+        // the data stays complete, checking on it tries to put it in place again
+        assert_eq!(head(&root, &options, &location).code, status::CONFLICT);
+        fs::remove_file(&target).unwrap();
+        let response = head(&root, &options, &location);
+        assert_eq!(response.code, status::OK);
+        assert_eq!(response.get_header("Upload-Offset"), Some("4"));
+        assert_eq!(fs::read(&target).unwrap(), b"data");
+        assert_eq!(head(&root, &options, &location).code, status::NOT_FOUND);
+
+        // the url of an upload is answered by the server, a file there could never be reached
+        let response = create(&root, &options, "4", "filename LnR1cw==");
+        assert_eq!(response.code, status::FORBIDDEN);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn parse_upload_metadata() {
+        assert_eq!(
+            decode_base64("d29ybGRfZG9taW5hdGlvbl9wbGFuLnBkZg==").unwrap(),
+            b"world_domination_plan.pdf"
+        );
+        assert_eq!(decode_base64("YQ").unwrap(), b"a");
+        assert!(decode_base64("a*b").is_none());
+
+        let pairs =
+            parse_metadata("filename 5paH5Lu2LnR4dA==,is_confidential, dir ZG9jcw==").unwrap();
+        assert_eq!(
+            pairs,
+            vec![
+                (String::from("filename"), String::from("文件.txt")),
+                (String::from("is_confidential"), String::new()),
+                (String::from("dir"), String::from("docs")),
+            ]
+        );
+        assert!(parse_metadata("filename //79").is_none());
+    }
+}
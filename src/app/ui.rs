@@ -1,18 +1,77 @@
 use std::cell::RefCell;
+use std::path::Path;
 use std::rc::Rc;
 
-use fltk::dialog::{self, NativeFileChooser};
+use fltk::{
+    button::Button,
+    dialog::{self, NativeFileChooser},
+    frame::Frame,
+    prelude::*,
+    window::Window,
+};
 
+use crate::app::i18n::Locale;
 use crate::app::middleware;
+use crate::app::thumbnail;
 use crate::app::state::AppState;
+use crate::infra::https::HttpsServerStatus;
+
+/// The widgets with a translatable label
+#[derive(Clone)]
+struct Labels {
+    wind: Window,
+    language: Frame,
+    cert: Frame,
+    key: Frame,
+    root: Frame,
+    but_cert: Button,
+    but_key: Button,
+    but_root: Button,
+    but_start: Button,
+}
+
+impl Labels {
+    /// Relabels every widget in the language of the state
+    fn update(&mut self, state: &AppState) {
+        let locale = state.locale;
+        let chosen = |path: &Option<String>| {
+            path.as_deref()
+                .and_then(|path| Path::new(path).file_name())
+                .and_then(|name| name.to_str())
+                .unwrap_or(locale.text("Click to choose"))
+                .to_string()
+        };
+        self.wind.set_label(locale.text("Secure Web Server (OpenSSL)"));
+        self.language.set_label(locale.text("Language:"));
+        self.cert.set_label(locale.text("Cert:"));
+        self.key.set_label(locale.text("Private Key:"));
+        self.root.set_label(locale.text("Root Directory:"));
+        self.but_cert.set_label(&chosen(&state.server.cert));
+        self.but_key.set_label(&chosen(&state.server.key));
+        self.but_root.set_label(&chosen(&state.root_directory));
+        self.but_start.set_label(match state.server.status {
+            HttpsServerStatus::Started => locale.text("Stop"),
+            _ => locale.text("Start"),
+        });
+        self.wind.redraw();
+    }
+}
 
 pub fn launch(state: Rc<RefCell<AppState>>) {
-    use fltk::{app, button::Button, frame::Frame, prelude::*, window::Window};
+    use fltk::{app, menu::Choice};
     let app = fltk::app::App::default().with_scheme(app::Scheme::Gtk);
-    let mut wind = Window::new(100, 100, 400, 300, "基于OpenSSL的安全Web服务器程序");
+    let wind = Window::new(100, 100, 400, 300, None);
+
+    let language = Frame::new(90, 10, 30, 30, None);
+    let mut choice = Choice::new(140, 10, 150, 30, None);
+    for locale in Locale::ALL {
+        choice.add_choice(locale.name());
+    }
+    let current = Locale::ALL.iter().position(|locale| *locale == state.borrow().locale);
+    choice.set_value(current.unwrap_or(0) as i32);
 
-    let _ = Frame::new(100, 50, 30, 30, "Cert:");
-    let mut but_cert = Button::new(140, 50, 150, 30, "Click To Chose");
+    let cert = Frame::new(100, 50, 30, 30, None);
+    let mut but_cert = Button::new(140, 50, 150, 30, None);
     let cloned_state = state.clone();
     but_cert.set_callback(move |but| {
         let mut chooser_cert = NativeFileChooser::new(dialog::FileDialogType::BrowseFile);
@@ -23,7 +82,7 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
                 .file_name()
                 .unwrap_or_default()
                 .to_str()
-                .unwrap_or("Click To Chose"),
+                .unwrap_or(cloned_state.borrow().locale.text("Click to choose")),
         );
         cloned_state.borrow_mut().server.cert = chooser_cert
             .filename()
@@ -32,8 +91,8 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
             .and_then(|str| Some(str.to_string()));
     });
 
-    let _ = Frame::new(80, 90, 30, 30, "Private Key:");
-    let mut but_key = Button::new(140, 90, 150, 30, "Click To Chose");
+    let key = Frame::new(80, 90, 30, 30, None);
+    let mut but_key = Button::new(140, 90, 150, 30, None);
     let cloned_state = state.clone();
     but_key.set_callback(move |but| {
         let mut chooser_cert = NativeFileChooser::new(dialog::FileDialogType::BrowseFile);
@@ -44,7 +103,7 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
                 .file_name()
                 .unwrap_or_default()
                 .to_str()
-                .unwrap_or("Click To Chose"),
+                .unwrap_or(cloned_state.borrow().locale.text("Click to choose")),
         );
 
         cloned_state.borrow_mut().server.key = chooser_cert
@@ -54,8 +113,8 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
             .and_then(|str| Some(str.to_string()));
     });
 
-    let _ = Frame::new(60, 130, 30, 30, "Root Directory:");
-    let mut but_root = Button::new(140, 130, 150, 30, "Click To Chose");
+    let root = Frame::new(60, 130, 30, 30, None);
+    let mut but_root = Button::new(140, 130, 150, 30, None);
     let cloned_state = state.clone();
     but_root.set_callback(move |but| {
         let mut chooser_cert = NativeFileChooser::new(dialog::FileDialogType::BrowseDir);
@@ -66,7 +125,7 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
                 .file_name()
                 .unwrap_or_default()
                 .to_str()
-                .unwrap_or("Click To Chose"),
+                .unwrap_or(cloned_state.borrow().locale.text("Click to choose")),
         );
         cloned_state.borrow_mut().root_directory = chooser_cert
             .filename()
@@ -75,21 +134,22 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
             .and_then(|str| Some(str.to_string()));
     });
 
-    let mut but_start = Button::new(120, 170, 150, 30, "Start");
+    let mut but_start = Button::new(120, 170, 150, 30, None);
     let cloned_state = state.clone();
     but_start.set_callback(move |but| {
         let mut state = cloned_state.borrow_mut();
+        let locale = state.locale;
         match state.server.status {
-            crate::infra::https::HttpsServerStatus::Started => {
+            HttpsServerStatus::Started => {
                 state.server.shutdown().unwrap();
-                but.set_label("Start");
+                but.set_label(locale.text("Start"));
             }
-            crate::infra::https::HttpsServerStatus::Stopped => {
+            HttpsServerStatus::Stopped => {
                 if state.server.cert.is_none()
                     || state.server.key.is_none()
                     || state.root_directory.is_none()
                 {
-                    dialog::alert_default("Arguments Not Ready");
+                    dialog::alert_default(locale.text("Arguments Not Ready"));
                     return;
                 }
                 state.server.bind_addr = Some(String::from("0.0.0.0:443"));
@@ -98,18 +158,50 @@ pub fn launch(state: Rc<RefCell<AppState>>) {
                     .clone()
                     .unwrap_or(String::from("."))
                     .clone();
+                let options = state.options.clone();
+                // thumbnails cached there would be listed and served like the user's files
+                if thumbnail::is_inside(&options.thumbnail_dir, Path::new(&root_directory)) {
+                    dialog::alert_default(locale.text("Thumbnail Cache Inside Folder"));
+                    return;
+                }
                 state
                     .server
-                    .launch(middleware::static_middleware(root_directory))
+                    .launch(
+                        middleware::static_middleware(root_directory.clone(), options.clone()),
+                        Some(middleware::static_expect(root_directory, options)),
+                    )
                     .unwrap();
-                but.set_label("Stop");
+                but.set_label(locale.text("Stop"));
             }
             _ => {
-                dialog::alert_default("Server Busy");
+                dialog::alert_default(locale.text("Server Busy"));
             }
         }
     });
 
+    let mut labels = Labels {
+        wind,
+        language,
+        cert,
+        key,
+        root,
+        but_cert,
+        but_key,
+        but_root,
+        but_start,
+    };
+    labels.update(&state.borrow());
+    let cloned_state = state.clone();
+    let mut cloned_labels = labels.clone();
+    choice.set_callback(move |choice| {
+        let index = choice.value().max(0) as usize;
+        if let Some(locale) = Locale::ALL.get(index) {
+            cloned_state.borrow_mut().locale = *locale;
+            cloned_labels.update(&cloned_state.borrow());
+        }
+    });
+
+    let mut wind = labels.wind;
     wind.end();
     wind.show();
     app.run().unwrap();
@@ -1,4 +1,10 @@
+use std::path::{Path, PathBuf};
+
 use crate::app;
+use crate::infra::http::mime::{self, Priority};
+
+/// the system wide media types, only consulted for extensions the built-in table lacks
+const SYSTEM_MIME_TYPES: &str = "/etc/mime.types";
 
 pub fn boost() {
     let matches = clap::App::new("http-server-app")
@@ -40,12 +46,171 @@ pub fn boost() {
                 .help("disable gui")
                 .takes_value(false),
         )
+        .arg(
+            clap::Arg::with_name("strict")
+                .short("s")
+                .long("strict")
+                .help("reject requests that do not strictly follow RFC 9112")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("max-upload-size")
+                .long("max-upload-size")
+                .help("largest accepted size of a single uploaded file in bytes")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("staging-dir")
+                .long("staging-dir")
+                .help("directory for unfinished resumable uploads, outside the root")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("create-parents")
+                .long("create-parents")
+                .help("let PUT create missing parent directories")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("hide-dotfiles")
+                .long("hide-dotfiles")
+                .help("leave dotfiles out of directory listings and archives")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("max-archive-size")
+                .long("max-archive-size")
+                .help("largest total size in bytes of a directory downloaded as an archive")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("page-size")
+                .long("page-size")
+                .help("entries shown on one page of a directory listing")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("index")
+                .long("index")
+                .help("comma separated file names served for directory requests")
+                .default_value("index.html")
+                .use_delimiter(true)
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("no-listing")
+                .long("no-listing")
+                .help("forbid directories without an index file instead of listing them")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("spa")
+                .long("spa")
+                .help("file below the root served for unknown paths, for single-page apps")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("templates")
+                .long("templates")
+                .help("directory of .hbs files overriding the built-in templates")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("assets")
+                .long("assets")
+                .help("directory of css, scripts and icons served below /.assets/")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("no-compression")
+                .long("no-compression")
+                .help("send bodies uncompressed unless a precompressed .br or .gz file exists")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("compress-min-size")
+                .long("compress-min-size")
+                .help("smallest body in bytes compressed on the fly")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("preview")
+                .long("preview")
+                .help("show browsers Markdown rendered and source code highlighted, ?raw=1 gives the file")
+                .takes_value(false),
+        )
+        .arg(
+            clap::Arg::with_name("thumbnail-dir")
+                .long("thumbnail-dir")
+                .help("directory caching image thumbnails of the gallery view, outside the root")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("mime-types")
+                .long("mime-types")
+                .help("file in the mime.types format overriding the built-in media types")
+                .takes_value(true),
+        )
+        .arg(
+            clap::Arg::with_name("dev")
+                .long("dev")
+                .help("reload templates on every request")
+                .takes_value(false),
+        )
         .get_matches();
+    let mut options = app::StaticOptions::new();
+    if let Some(size) = matches.value_of("max-upload-size") {
+        options.max_part_size = size
+            .parse()
+            .expect("max-upload-size should be a number of bytes");
+    }
+    if let Some(staging_dir) = matches.value_of("staging-dir") {
+        options.staging_dir = PathBuf::from(staging_dir);
+    }
+    if let Some(thumbnail_dir) = matches.value_of("thumbnail-dir") {
+        options.thumbnail_dir = PathBuf::from(thumbnail_dir);
+    }
+    options.create_parents = matches.is_present("create-parents");
+    options.show_hidden = !matches.is_present("hide-dotfiles");
+    if let Some(size) = matches.value_of("max-archive-size") {
+        options.max_archive_size = size
+            .parse()
+            .expect("max-archive-size should be a number of bytes");
+    }
+    if let Some(size) = matches.value_of("page-size") {
+        options.page_size = size
+            .parse()
+            .expect("page-size should be a number of entries");
+    }
+    options.index_files = matches
+        .values_of("index")
+        .map(|names| names.filter(|name| !name.is_empty()).map(String::from).collect())
+        .unwrap_or_default();
+    options.auto_index = !matches.is_present("no-listing");
+    options.spa_fallback = matches.value_of("spa").map(PathBuf::from);
+    options.templates_dir = matches.value_of("templates").map(PathBuf::from);
+    options.assets_dir = matches.value_of("assets").map(PathBuf::from);
+    options.hot_reload = matches.is_present("dev");
+    options.compression = !matches.is_present("no-compression");
+    options.preview = matches.is_present("preview");
+    if let Some(size) = matches.value_of("compress-min-size") {
+        options.compress_min_size = size
+            .parse()
+            .expect("compress-min-size should be a number of bytes");
+    }
+    // most systems other than Debian and Red Hat derivatives have no such file
+    mime::load(Path::new(SYSTEM_MIME_TYPES), Priority::Fallback).ok();
+    if let Some(path) = matches.value_of("mime-types") {
+        mime::load(Path::new(path), Priority::Override)
+            .expect("mime-types should be a readable mime.types file");
+    }
     app::run(
         matches.value_of("cert"),
         matches.value_of("key"),
         matches.value_of("bind"),
         matches.value_of("root"),
         !matches.is_present("no-gui"),
+        matches.is_present("strict"),
+        options,
     );
 }
@@ -0,0 +1,300 @@
+use std::io::{self, Read};
+
+use super::{
+    form_data::FormData,
+    header,
+    message::{HttpMessage, Request, Response},
+    status::{self, Status},
+    url::Query,
+    Error,
+};
+
+/// A request body decoded according to its Content-Type
+#[derive(Debug, Clone)]
+pub enum Body {
+    Empty,
+    Form(Query),
+    Json(serde_json::Value),
+    Multipart(FormData),
+}
+
+/// Why a body could not be decoded, along with the status to answer with
+#[derive(Debug)]
+pub struct BodyError {
+    pub code: Status,
+    pub error: Error,
+}
+
+impl BodyError {
+    fn new(code: Status, message: &'static str) -> Self {
+        Self {
+            code,
+            error: Error::new(message),
+        }
+    }
+    pub fn to_response(&self) -> Response {
+        Response::with_text(self.code, &self.error.to_string())
+    }
+}
+
+impl std::fmt::Display for BodyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{} {}", self.code, self.error)
+    }
+}
+
+impl std::error::Error for BodyError {}
+
+impl Body {
+    /// Reads at most `limit` bytes of the body and decodes it
+    ///
+    /// Example
+    /// ```ignore
+    /// match Body::from_request(&mut request, 1024 * 1024) {
+    ///     Ok(Body::Json(value)) => { /* ... */ }
+    ///     Ok(_) => Response::with_text(status::UNSUPPORTED_MEDIA_TYPE, "expected json"),
+    ///     Err(err) => err.to_response(),
+    /// }
+    /// ```
+    pub fn from_request(request: &mut Request, limit: u64) -> Result<Self, BodyError> {
+        // the media type is checked first, so a body that cannot be decoded is never read
+        let media_type = match media_type(request)? {
+            Some(media_type) => media_type,
+            None => return Ok(Body::Empty),
+        };
+        let raw = read_limited(request, limit)?;
+        match media_type {
+            MediaType::Form(charset) => {
+                let form = Query::parse_with_charset(&raw, &charset).map_err(|err| BodyError {
+                    code: status::BAD_REQUEST,
+                    error: err,
+                })?;
+                Ok(Body::Form(form))
+            }
+            MediaType::Json => {
+                let raw = raw.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(&raw);
+                let value = serde_json::from_slice(raw)
+                    .map_err(|_| BodyError::new(status::BAD_REQUEST, "malformed json body"))?;
+                Ok(Body::Json(value))
+            }
+            MediaType::Multipart(boundary) => {
+                let form_data = FormData::parse(boundary.as_bytes(), &raw)
+                    .map_err(|_| BodyError::new(status::BAD_REQUEST, "malformed multipart body"))?;
+                Ok(Body::Multipart(form_data))
+            }
+        }
+    }
+    pub fn form(&self) -> Option<&Query> {
+        match self {
+            Body::Form(form) => Some(form),
+            _ => None,
+        }
+    }
+    pub fn json(&self) -> Option<&serde_json::Value> {
+        match self {
+            Body::Json(value) => Some(value),
+            _ => None,
+        }
+    }
+    pub fn multipart(&self) -> Option<&FormData> {
+        match self {
+            Body::Multipart(form_data) => Some(form_data),
+            _ => None,
+        }
+    }
+}
+
+/// How a body is decoded, decided from the request head alone
+enum MediaType {
+    /// with its charset
+    Form(String),
+    Json,
+    /// with its boundary
+    Multipart(String),
+}
+
+/// The media type of the body, or None for a request without one
+fn media_type(request: &Request) -> Result<Option<MediaType>, BodyError> {
+    let has_body = !request.body.is_empty()
+        || request
+            .body_reader
+            .as_ref()
+            .is_some_and(|body_reader| body_reader.remaining() > 0);
+    let content_type = match request.get_header("Content-Type") {
+        Some(content_type) => content_type,
+        None if !has_body => return Ok(None),
+        None => {
+            return Err(BodyError::new(
+                status::UNSUPPORTED_MEDIA_TYPE,
+                "missing Content-Type",
+            ))
+        }
+    };
+    let (media_type, params) = header::parse_params(content_type);
+    let charset = header::get_param(&params, "charset");
+    let unsupported_charset =
+        || BodyError::new(status::UNSUPPORTED_MEDIA_TYPE, "unsupported charset");
+
+    if media_type.eq_ignore_ascii_case("application/x-www-form-urlencoded") {
+        let charset = charset.unwrap_or("UTF-8");
+        if !charset.eq_ignore_ascii_case("UTF-8") && !charset.eq_ignore_ascii_case("ISO-8859-1") {
+            return Err(unsupported_charset());
+        }
+        Ok(Some(MediaType::Form(String::from(charset))))
+    } else if is_json(&media_type) {
+        // JSON is always UTF-8, see RFC 8259 section 8.1
+        if matches!(charset, Some(charset) if !charset.eq_ignore_ascii_case("UTF-8")) {
+            return Err(unsupported_charset());
+        }
+        Ok(Some(MediaType::Json))
+    } else if media_type.eq_ignore_ascii_case("multipart/form-data") {
+        let boundary = FormData::boundary(content_type).map_err(|err| BodyError {
+            code: status::BAD_REQUEST,
+            error: err,
+        })?;
+        Ok(Some(MediaType::Multipart(boundary)))
+    } else if !has_body {
+        Ok(None)
+    } else {
+        Err(BodyError::new(
+            status::UNSUPPORTED_MEDIA_TYPE,
+            "unsupported Content-Type",
+        ))
+    }
+}
+
+/// `application/json` and structured syntax suffixes like `application/problem+json`
+fn is_json(media_type: &str) -> bool {
+    let media_type = media_type.to_ascii_lowercase();
+    media_type == "application/json"
+        || (media_type.starts_with("application/") && media_type.ends_with("+json"))
+}
+
+fn read_limited(request: &mut Request, limit: u64) -> Result<Vec<u8>, BodyError> {
+    let too_large = || BodyError::new(status::PAYLOAD_TOO_LARGE, "request body too large");
+    let mut raw = std::mem::take(&mut request.body);
+    if raw.len() as u64 > limit {
+        return Err(too_large());
+    }
+    if let Some(body_reader) = request.body_reader.take() {
+        // reject up front instead of reading a body that is known to be too large
+        if body_reader.remaining() > limit - raw.len() as u64 {
+            request.body_reader = Some(body_reader);
+            return Err(too_large());
+        }
+        body_reader
+            .take(limit - raw.len() as u64)
+            .read_to_end(&mut raw)
+            .map_err(|err| match err.kind() {
+                io::ErrorKind::UnexpectedEof => {
+                    BodyError::new(status::BAD_REQUEST, "incomplete request body")
+                }
+                _ => BodyError::new(status::BAD_REQUEST, "failed to read request body"),
+            })?;
+    }
+    Ok(raw)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, collections::HashMap, io::Cursor, rc::Rc};
+
+    use super::Body;
+    use crate::infra::http::{
+        message::{BodyReader, Request},
+        status,
+        url::Url,
+    };
+
+    fn request(content_type: Option<&str>, body: &[u8]) -> Request {
+        let mut headers = HashMap::new();
+        if let Some(content_type) = content_type {
+            headers.insert(String::from("content-type"), String::from(content_type));
+        }
+        Request {
+            method: String::from("POST"),
+            path: String::from("/"),
+            url: Url::parse(b"/").unwrap(),
+            version: String::from("HTTP/1.1"),
+            headers,
+            body: Vec::from(body),
+            body_reader: None,
+        }
+    }
+
+    #[test]
+    fn decode_by_content_type() {
+        let mut form = request(
+            Some("application/x-www-form-urlencoded; charset=ISO-8859-1"),
+            b"name=%A3&q=a+b",
+        );
+        let body = Body::from_request(&mut form, 1024).unwrap();
+        assert_eq!(body.form().unwrap().get("name"), Some("£"));
+        assert_eq!(body.form().unwrap().get("q"), Some("a b"));
+
+        let mut json = request(Some("Application/JSON"), b"{\"name\":\"tom\"}");
+        let body = Body::from_request(&mut json, 1024).unwrap();
+        assert_eq!(body.json().unwrap()["name"], "tom");
+
+        let mut multipart = request(
+            Some("multipart/form-data; boundary=b"),
+            b"--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n1\r\n--b--\r\n",
+        );
+        let body = Body::from_request(&mut multipart, 1024).unwrap();
+        assert_eq!(body.multipart().unwrap().get_part("a").unwrap().data, b"1");
+
+        let mut empty = request(None, b"");
+        assert!(matches!(
+            Body::from_request(&mut empty, 1024).unwrap(),
+            Body::Empty
+        ));
+    }
+
+    #[test]
+    fn reject_bodies() {
+        let cases: [(Option<&str>, &[u8], u64, u16); 6] = [
+            (
+                Some("text/plain"),
+                b"hello",
+                1024,
+                status::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (None, b"hello", 1024, status::UNSUPPORTED_MEDIA_TYPE),
+            (
+                Some("application/json; charset=utf-16"),
+                b"{}",
+                1024,
+                status::UNSUPPORTED_MEDIA_TYPE,
+            ),
+            (Some("application/json"), b"{", 1024, status::BAD_REQUEST),
+            (
+                Some("application/x-www-form-urlencoded"),
+                b"name=%FF",
+                1024,
+                status::BAD_REQUEST,
+            ),
+            (
+                Some("application/json"),
+                b"[1,2,3]",
+                4,
+                status::PAYLOAD_TOO_LARGE,
+            ),
+        ];
+        for (content_type, body, limit, code) in cases {
+            let mut request = request(content_type, body);
+            let err = Body::from_request(&mut request, limit).unwrap_err();
+            assert_eq!(err.code, code, "{:?}", content_type);
+        }
+    }
+
+    #[test]
+    fn reject_before_reading() {
+        let connection = Rc::new(RefCell::new(Cursor::new(Vec::from("hello"))));
+        let mut request = request(Some("text/plain"), b"");
+        request.body_reader = Some(BodyReader::new(connection.clone(), 5));
+        let err = Body::from_request(&mut request, 1024).unwrap_err();
+        assert_eq!(err.code, status::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(request.body_reader.unwrap().remaining(), 5);
+        assert_eq!(connection.borrow().position(), 0);
+    }
+}
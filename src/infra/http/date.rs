@@ -0,0 +1,111 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Formats `time` as an IMF-fixdate like `Sun, 06 Nov 1994 08:49:37 GMT`, see RFC 9110 section 5.6.7
+pub fn format(time: SystemTime) -> String {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let days = seconds / 86400;
+    let (year, month, day, rest) = to_civil(time);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[(days % 7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        rest / 3600,
+        rest % 3600 / 60,
+        rest % 60
+    )
+}
+
+/// Splits `time` into the UTC year, month, day and seconds since midnight
+pub fn to_civil(time: SystemTime) -> (i64, u32, u32, u32) {
+    let seconds = time
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or(Duration::ZERO)
+        .as_secs();
+    let (year, month, day) = civil_from_days((seconds / 86400) as i64);
+    (year, month, day, (seconds % 86400) as u32)
+}
+
+/// Parses an IMF-fixdate, the obsolete formats are not accepted
+pub fn parse(value: &str) -> Option<SystemTime> {
+    let (_, rest) = value.trim().split_once(", ")?;
+    let fields: Vec<&str> = rest.split(' ').collect();
+    if fields.len() != 5 || fields[4] != "GMT" {
+        return None;
+    }
+    let day: u32 = fields[0].parse().ok()?;
+    let month = MONTHS.iter().position(|month| *month == fields[1])? as u32 + 1;
+    let year: i64 = fields[2].parse().ok()?;
+    let time: Vec<u64> = fields[3]
+        .split(':')
+        .map(|field| field.parse().ok())
+        .collect::<Option<_>>()?;
+    if time.len() != 3 || day == 0 || day > 31 || time[0] > 23 || time[1] > 59 || time[2] > 60 {
+        return None;
+    }
+    let days = days_from_civil(year, month, day);
+    if days < 0 {
+        return None;
+    }
+    let seconds = days as u64 * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Some(UNIX_EPOCH + Duration::from_secs(seconds))
+}
+
+/// see http://howardhinnant.github.io/date_algorithms.html
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = month as i64;
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{format, parse};
+
+    #[test]
+    fn format_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(format(time), "Sun, 06 Nov 1994 08:49:37 GMT");
+        assert_eq!(format(UNIX_EPOCH), "Thu, 01 Jan 1970 00:00:00 GMT");
+        let leap = UNIX_EPOCH + Duration::from_secs(951782400);
+        assert_eq!(format(leap), "Tue, 29 Feb 2000 00:00:00 GMT");
+    }
+
+    #[test]
+    fn parse_http_date() {
+        let time = UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 GMT"), Some(time));
+        assert_eq!(parse(&format(time)), Some(time));
+        assert_eq!(parse("Sunday, 06-Nov-94 08:49:37 GMT"), None);
+        assert_eq!(parse("Sun, 06 Nov 1994 08:49:37 UTC"), None);
+        assert_eq!(parse("Sun, 06 Foo 1994 08:49:37 GMT"), None);
+    }
+}
@@ -1,12 +1,24 @@
-mod fsm;
+mod builder;
+mod reader;
 
-use rust_fsm::*;
+use std::io::Read;
+
+use super::{
+    header,
+    message::{HttpMessage, Request},
+    Error,
+};
+
+pub use self::builder::MultipartBuilder;
+pub use self::reader::{MultipartReader, Part};
 
 #[derive(Clone, Debug)]
 pub struct FormDataPart {
     pub name: Option<String>,
     pub filename: Option<String>,
     pub content_type: Option<String>,
+    /// every header of the part as sent, including the ones parsed into the fields above
+    pub headers: Vec<(String, String)>,
     pub data: Vec<u8>,
 }
 
@@ -16,9 +28,46 @@ impl FormDataPart {
             content_type: None,
             data: Vec::new(),
             filename: None,
+            headers: Vec::new(),
             name: None,
         }
     }
+    /// first header named `key`, compared case-insensitively
+    pub fn get_header(&self, key: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(key))
+            .map(|(_, value)| value.as_str())
+    }
+    fn apply_header(&mut self, header_field: &str, header_value: &str) {
+        self.headers
+            .push((String::from(header_field), String::from(header_value)));
+        if header_field.eq_ignore_ascii_case("Content-Disposition") {
+            // see RFC 7578 section 4.2, `filename*` wins over `filename` when it decodes
+            let (_, params) = header::parse_params(header_value);
+            if let Some(name) = header::get_param(&params, "name") {
+                self.name = Some(String::from(name));
+            }
+            let filename = header::get_param(&params, "filename*")
+                .and_then(header::decode_ext_value)
+                .or_else(|| header::get_param(&params, "filename").map(String::from));
+            if filename.is_some() {
+                self.filename = filename;
+            }
+        } else if header_field.eq_ignore_ascii_case("Content-Type") {
+            self.content_type = Some(String::from(header_value));
+        }
+    }
+}
+
+/// bchars, see RFC 2046 section 5.1.1
+fn is_valid_boundary(boundary: &str) -> bool {
+    !boundary.is_empty()
+        && boundary.len() <= 70
+        && !boundary.ends_with(' ')
+        && boundary
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"'()+_,-./:=? ".contains(&byte))
 }
 
 #[derive(Clone, Debug)]
@@ -27,6 +76,12 @@ pub struct FormData {
     pub parts: Vec<FormDataPart>,
 }
 
+impl Default for FormData {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl FormData {
     pub fn new() -> Self {
         Self {
@@ -38,225 +93,179 @@ impl FormData {
         for part in &self.parts {
             if let Some(part_name) = &part.name {
                 if part_name == name {
-                    return Some(&part);
+                    return Some(part);
                 }
             }
         }
         None
     }
-    pub fn parse(raw: &[u8]) -> Result<Option<Self>, Box<dyn std::error::Error>> {
-        let mut machine: StateMachine<fsm::FormData> = StateMachine::new();
+    /// Every part sent under `name`, in the order they appear in the body
+    pub fn get_all(&self, name: &str) -> Vec<&FormDataPart> {
+        self.parts
+            .iter()
+            .filter(|part| part.name.as_deref() == Some(name))
+            .collect()
+    }
+    /// Takes the boundary parameter from a `multipart/form-data` Content-Type
+    pub fn boundary(content_type: &str) -> Result<String, Error> {
+        let (media_type, params) = header::parse_params(content_type);
+        if !media_type.eq_ignore_ascii_case("multipart/form-data") {
+            return Err(Error::new("expected multipart/form-data"));
+        }
+        let boundary =
+            header::get_param(&params, "boundary").ok_or(Error::new("missing multipart boundary"))?;
+        if !is_valid_boundary(boundary) {
+            return Err(Error::new("invalid multipart boundary"));
+        }
+        Ok(String::from(boundary))
+    }
+    pub fn from_request(request: &Request) -> Result<Self, Box<dyn std::error::Error>> {
+        let content_type = request
+            .get_header("Content-Type")
+            .ok_or(Error::new("missing Content-Type"))?;
+        let boundary = FormData::boundary(content_type)?;
+        match request.body_reader.clone() {
+            Some(body_reader) => FormData::read(boundary.as_bytes(), body_reader),
+            None => FormData::parse(boundary.as_bytes(), &request.body),
+        }
+    }
+    /// Reads every part into memory, prefer `MultipartReader` for large bodies
+    pub fn read<R: Read>(boundary: &[u8], reader: R) -> Result<Self, Box<dyn std::error::Error>> {
         let mut form_data = FormData::new();
-        let mut form_data_part = FormDataPart::new();
-        let mut header_field: Vec<u8> = Vec::new();
-        let mut header_value: Vec<u8> = Vec::new();
-        let mut boundary_like: Vec<u8> = Vec::new();
-
-        for byte in raw.iter() {
-            let byte = byte.clone();
-            let effect = machine.consume(match byte {
-                b'-' if machine.state() != &fsm::FormDataState::Boundary
-                    && machine.state() != &fsm::FormDataState::End
-                    && boundary_like == form_data.boundary =>
-                {
-                    &fsm::FormDataInput::EndDash
-                }
-                _ if (machine.state() == &fsm::FormDataState::Data
-                    || machine.state() == &fsm::FormDataState::DataToBoundary)
-                    && form_data.boundary[boundary_like.len()] == byte =>
-                {
-                    &fsm::FormDataInput::BoundaryLike
-                }
-                b'-' => &fsm::FormDataInput::Dash,
-                b'\r' => &fsm::FormDataInput::Cr,
-                b'\n' => &fsm::FormDataInput::Lf,
-                b':' => &fsm::FormDataInput::Colon,
-                b' ' => &fsm::FormDataInput::Blank,
-                _ => &fsm::FormDataInput::Alpha,
-            })?;
-
-            match effect {
-                Some(effect) => match effect {
-                    fsm::FormDataOutput::EffectAppendBoundary => {
-                        form_data.boundary.push(byte);
-                    }
-                    fsm::FormDataOutput::EffectAppendData => {
-                        if !boundary_like.is_empty() {
-                            form_data_part.data.append(&mut boundary_like);
-                        }
-                        form_data_part.data.push(byte);
-                    }
-                    fsm::FormDataOutput::EffectAppendHeader => {
-                        let mut field = Vec::new();
-                        field.append(&mut header_field);
-                        let header_field = String::from_utf8(field)?;
-
-                        let mut value = Vec::new();
-                        value.append(&mut header_value);
-
-                        match header_field.as_str() {
-                            "Content-Disposition" => {
-                                let header_value = String::from_utf8(value)?;
-                                let pairs: Vec<Vec<&str>> = header_value
-                                    .split("; ")
-                                    .map(|item| -> Vec<&str> { item.splitn(2, "=").collect() })
-                                    .collect();
-                                for pair in pairs {
-                                    if pair.len() >= 2 {
-                                        match pair[0] {
-                                            "name" => {
-                                                form_data_part.name = Some(String::from(
-                                                    &pair[1][1..(pair[1].len() - 1)],
-                                                ));
-                                            }
-                                            "filename" => {
-                                                form_data_part.filename = Some(String::from(
-                                                    &pair[1][1..(pair[1].len() - 1)],
-                                                ));
-                                            }
-                                            _ => {}
-                                        }
-                                    }
-                                }
-                            }
-                            "Content-Type" => {
-                                form_data_part.content_type = Some(String::from_utf8(value)?);
-                            }
-                            _ => {}
-                        }
-                    }
-                    fsm::FormDataOutput::EffectAppendHeaderField => {
-                        header_field.push(byte);
-                    }
-                    fsm::FormDataOutput::EffectAppendHeaderValue => {
-                        header_value.push(byte);
-                    }
-                    fsm::FormDataOutput::EffectAppendLikeBoundary => {
-                        boundary_like.push(byte);
-                    }
-                    fsm::FormDataOutput::EffectAppendPart => {
-                        form_data.parts.push(form_data_part.clone());
-                    }
-                    fsm::FormDataOutput::EffectFormData => {
-                        machine.consume(&fsm::FormDataInput::End).unwrap();
-                        return Ok(Some(form_data));
-                    }
-                },
-                None => {
-                    // do nothing
-                }
-            }
+        form_data.boundary = Vec::from(boundary);
+        let mut reader = MultipartReader::new(reader, boundary);
+        while let Some(mut part) = reader.next_part()? {
+            let mut data = Vec::new();
+            part.read_to_end(&mut data)?;
+            let mut form_data_part = part.info;
+            form_data_part.data = data;
+            form_data.parts.push(form_data_part);
         }
-        Ok(None)
+        Ok(form_data)
+    }
+    pub fn parse(boundary: &[u8], raw: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        FormData::read(boundary, raw)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use super::FormData;
-    /// Example
-    /// ------WebKitFormBoundarype6X79pAiSSGJJKV
-    /// Content-Disposition: form-data; name="file"; filename="aria2.conf"
-    /// Content-Type: application/octet-stream
-    ///
-    /// # rpc-user=chenks
-    /// # rpc-passwd=749923710
-    /// rpc-secret=token
-    /// enable-rpc=true
-    /// rpc-allow-origin-all=true
-    /// rpc-listen-all=true
-    /// max-concurrent-downloads=5
-    /// continue=true
-    /// max-connection-per-server=5
-    /// min-split-size=10M
-    /// split=10
-    /// max-overall-download-limit=0
-    /// max-download-limit=0
-    /// max-overall-upload-limit=0
-    /// max-upload-limit=0
-    /// dir=/Users/brucezhou/movie
-    /// file-allocation=prealloc
-    /// ------WebKitFormBoundarype6X79pAiSSGJJKV--
+    use super::{builder::MultipartBuilder, FormData};
+
+    /// the body of a browser upload of aria2.conf next to a `dir` field
     #[test]
     fn test_form_date_parse() {
-        let data = vec![
-            45, 45, 45, 45, 45, 45, 87, 101, 98, 75, 105, 116, 70, 111, 114, 109, 66, 111, 117,
-            110, 100, 97, 114, 121, 112, 101, 54, 88, 55, 57, 112, 65, 105, 83, 83, 71, 74, 74, 75,
-            86, 13, 10, 67, 111, 110, 116, 101, 110, 116, 45, 68, 105, 115, 112, 111, 115, 105,
-            116, 105, 111, 110, 58, 32, 102, 111, 114, 109, 45, 100, 97, 116, 97, 59, 32, 110, 97,
-            109, 101, 61, 34, 102, 105, 108, 101, 34, 59, 32, 102, 105, 108, 101, 110, 97, 109,
-            101, 61, 34, 97, 114, 105, 97, 50, 46, 99, 111, 110, 102, 34, 13, 10, 67, 111, 110,
-            116, 101, 110, 116, 45, 84, 121, 112, 101, 58, 32, 97, 112, 112, 108, 105, 99, 97, 116,
-            105, 111, 110, 47, 111, 99, 116, 101, 116, 45, 115, 116, 114, 101, 97, 109, 13, 10, 13,
-            10, 35, 32, 114, 112, 99, 45, 117, 115, 101, 114, 61, 99, 104, 101, 110, 107, 115, 10,
-            35, 32, 114, 112, 99, 45, 112, 97, 115, 115, 119, 100, 61, 55, 52, 57, 57, 50, 51, 55,
-            49, 48, 10, 114, 112, 99, 45, 115, 101, 99, 114, 101, 116, 61, 116, 111, 107, 101, 110,
-            10, 101, 110, 97, 98, 108, 101, 45, 114, 112, 99, 61, 116, 114, 117, 101, 10, 114, 112,
-            99, 45, 97, 108, 108, 111, 119, 45, 111, 114, 105, 103, 105, 110, 45, 97, 108, 108, 61,
-            116, 114, 117, 101, 10, 114, 112, 99, 45, 108, 105, 115, 116, 101, 110, 45, 97, 108,
-            108, 61, 116, 114, 117, 101, 10, 109, 97, 120, 45, 99, 111, 110, 99, 117, 114, 114,
-            101, 110, 116, 45, 100, 111, 119, 110, 108, 111, 97, 100, 115, 61, 53, 10, 99, 111,
-            110, 116, 105, 110, 117, 101, 61, 116, 114, 117, 101, 10, 109, 97, 120, 45, 99, 111,
-            110, 110, 101, 99, 116, 105, 111, 110, 45, 112, 101, 114, 45, 115, 101, 114, 118, 101,
-            114, 61, 53, 10, 109, 105, 110, 45, 115, 112, 108, 105, 116, 45, 115, 105, 122, 101,
-            61, 49, 48, 77, 10, 115, 112, 108, 105, 116, 61, 49, 48, 10, 109, 97, 120, 45, 111,
-            118, 101, 114, 97, 108, 108, 45, 100, 111, 119, 110, 108, 111, 97, 100, 45, 108, 105,
-            109, 105, 116, 61, 48, 10, 109, 97, 120, 45, 100, 111, 119, 110, 108, 111, 97, 100, 45,
-            108, 105, 109, 105, 116, 61, 48, 10, 109, 97, 120, 45, 111, 118, 101, 114, 97, 108,
-            108, 45, 117, 112, 108, 111, 97, 100, 45, 108, 105, 109, 105, 116, 61, 48, 10, 109, 97,
-            120, 45, 117, 112, 108, 111, 97, 100, 45, 108, 105, 109, 105, 116, 61, 48, 10, 100,
-            105, 114, 61, 47, 85, 115, 101, 114, 115, 47, 98, 114, 117, 99, 101, 122, 104, 111,
-            117, 47, 109, 111, 118, 105, 101, 10, 102, 105, 108, 101, 45, 97, 108, 108, 111, 99,
-            97, 116, 105, 111, 110, 61, 112, 114, 101, 97, 108, 108, 111, 99, 13, 10, 45, 45, 45,
-            45, 45, 45, 87, 101, 98, 75, 105, 116, 70, 111, 114, 109, 66, 111, 117, 110, 100, 97,
-            114, 121, 112, 101, 54, 88, 55, 57, 112, 65, 105, 83, 83, 71, 74, 74, 75, 86, 45, 45,
-            13, 10,
-        ];
-        let form_data = FormData::parse(&data);
-        let form_data = form_data.unwrap().unwrap();
+        let conf = "# rpc-user=chenks\nrpc-secret=token\nenable-rpc=true\ndir=/Users/brucezhou/movie\nfile-allocation=prealloc";
+        let mut builder = MultipartBuilder::form_data();
+        builder.boundary = String::from("----WebKitFormBoundarype6X79pAiSSGJJKV");
+        builder.field("dir", "movie").file(
+            "file",
+            "aria2.conf",
+            "application/octet-stream",
+            conf.as_bytes(),
+        );
+        let boundary = FormData::boundary(&builder.content_type()).unwrap();
+        let form_data = FormData::parse(boundary.as_bytes(), &builder.build()).unwrap();
+
+        let dir = form_data.get_part("dir").unwrap();
+        assert_eq!(dir.filename, None);
+        assert_eq!(dir.data, b"movie");
+        let file = form_data.get_part("file").unwrap();
+        assert_eq!(file.filename.as_deref(), Some("aria2.conf"));
+        assert_eq!(
+            file.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+        assert_eq!(file.data, conf.as_bytes());
+    }
+
+    #[test]
+    fn test_form_data_boundary() {
         assert_eq!(
-            form_data
-                .get_part("file")
-                .unwrap()
-                .filename
-                .as_ref()
+            FormData::boundary("multipart/form-data; boundary=----WebKitFormBoundaryyb1zYhTI38xpQxBK")
                 .unwrap(),
-            "aria2.conf"
+            "----WebKitFormBoundaryyb1zYhTI38xpQxBK"
         );
         assert_eq!(
-            form_data
-                .get_part("file")
-                .unwrap()
-                .content_type
-                .as_ref()
+            FormData::boundary("Multipart/Form-Data; charset=utf-8; boundary=\"simple boundary\"")
                 .unwrap(),
-            "application/octet-stream"
+            "simple boundary"
         );
+        assert!(FormData::boundary("multipart/form-data").is_err());
+        assert!(FormData::boundary("application/json; boundary=abc").is_err());
+        assert!(FormData::boundary("multipart/form-data; boundary=\"a\\\"b\"").is_err());
+    }
+
+    #[test]
+    fn test_form_data_parse_preamble_epilogue() {
+        let data = b"This is the preamble.\r\n--simple boundary\r\n\r\nimplicitly typed\r\n--simple boundary  \r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\nContent-Type: text/plain\r\n\r\n--simple boundar\r\n\r\n--simple boundary--\r\nThis is the epilogue.\r\n";
+        let form_data = FormData::parse(b"simple boundary", data).unwrap();
+        assert_eq!(form_data.parts.len(), 2);
+        assert_eq!(form_data.parts[0].data, b"implicitly typed");
+        assert_eq!(form_data.parts[0].name, None);
+        let part = form_data.get_part("file").unwrap();
+        assert_eq!(part.filename.as_deref(), Some("a.txt"));
+        assert_eq!(part.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(part.data, b"--simple boundar\r\n");
+    }
+
+    #[test]
+    fn test_form_data_get_all() {
+        let data = b"--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"a.txt\"\r\n\r\na\r\n--b\r\nContent-Disposition: form-data; name=\"file\"; filename=\"b.txt\"\r\n\r\nb\r\n--b\r\nContent-Disposition: form-data; name=\"overwrite\"\r\n\r\n1\r\n--b--\r\n";
+        let form_data = FormData::parse(b"b", data).unwrap();
+        let files = form_data.get_all("file");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].filename.as_deref(), Some("a.txt"));
+        assert_eq!(files[0].data, b"a");
+        assert_eq!(files[1].filename.as_deref(), Some("b.txt"));
+        assert_eq!(files[1].data, b"b");
+        // a later part does not inherit the filename of an earlier one
+        let overwrite = form_data.get_part("overwrite").unwrap();
+        assert_eq!(overwrite.filename, None);
+        assert_eq!(overwrite.data, b"1");
+        assert!(form_data.get_all("missing").is_empty());
+    }
+
+    #[test]
+    fn test_form_data_content_disposition() {
+        let data = "--b\r\ncontent-disposition:form-data ;name=\"a;b\\\"c\" ; filename=\"x; y.txt\"\r\nCONTENT-TYPE: text/plain\r\nX-Custom: 1\r\n\r\nfirst\r\n--b\r\nContent-Disposition: form-data; name=plain; filename=\"fallback.txt\"; filename*=UTF-8''%E2%82%AC%20rates.txt\r\n\r\nsecond\r\n--b\r\nContent-Disposition: form-data; name=\"bad\"; filename*=UTF-8''%FF; filename=kept.txt\r\n\r\nthird\r\n--b--\r\n";
+        let form_data = FormData::parse(b"b", data.as_bytes()).unwrap();
+
+        let first = &form_data.parts[0];
+        assert_eq!(first.name.as_deref(), Some("a;b\"c"));
+        assert_eq!(first.filename.as_deref(), Some("x; y.txt"));
+        assert_eq!(first.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(first.get_header("x-custom"), Some("1"));
+        assert_eq!(first.headers.len(), 3);
+
+        let second = form_data.get_part("plain").unwrap();
+        assert_eq!(second.filename.as_deref(), Some("€ rates.txt"));
+
+        let third = form_data.get_part("bad").unwrap();
+        assert_eq!(third.filename.as_deref(), Some("kept.txt"));
+    }
+
+    #[test]
+    fn test_form_data_parse_mismatch() {
+        let data = b"--other\r\n\r\ndata\r\n--other--\r\n";
         assert_eq!(
-            form_data.get_part("file").unwrap().data,
-            vec![
-                35, 32, 114, 112, 99, 45, 117, 115, 101, 114, 61, 99, 104, 101, 110, 107, 115, 10,
-                35, 32, 114, 112, 99, 45, 112, 97, 115, 115, 119, 100, 61, 55, 52, 57, 57, 50, 51,
-                55, 49, 48, 10, 114, 112, 99, 45, 115, 101, 99, 114, 101, 116, 61, 116, 111, 107,
-                101, 110, 10, 101, 110, 97, 98, 108, 101, 45, 114, 112, 99, 61, 116, 114, 117, 101,
-                10, 114, 112, 99, 45, 97, 108, 108, 111, 119, 45, 111, 114, 105, 103, 105, 110, 45,
-                97, 108, 108, 61, 116, 114, 117, 101, 10, 114, 112, 99, 45, 108, 105, 115, 116,
-                101, 110, 45, 97, 108, 108, 61, 116, 114, 117, 101, 10, 109, 97, 120, 45, 99, 111,
-                110, 99, 117, 114, 114, 101, 110, 116, 45, 100, 111, 119, 110, 108, 111, 97, 100,
-                115, 61, 53, 10, 99, 111, 110, 116, 105, 110, 117, 101, 61, 116, 114, 117, 101, 10,
-                109, 97, 120, 45, 99, 111, 110, 110, 101, 99, 116, 105, 111, 110, 45, 112, 101,
-                114, 45, 115, 101, 114, 118, 101, 114, 61, 53, 10, 109, 105, 110, 45, 115, 112,
-                108, 105, 116, 45, 115, 105, 122, 101, 61, 49, 48, 77, 10, 115, 112, 108, 105, 116,
-                61, 49, 48, 10, 109, 97, 120, 45, 111, 118, 101, 114, 97, 108, 108, 45, 100, 111,
-                119, 110, 108, 111, 97, 100, 45, 108, 105, 109, 105, 116, 61, 48, 10, 109, 97, 120,
-                45, 100, 111, 119, 110, 108, 111, 97, 100, 45, 108, 105, 109, 105, 116, 61, 48, 10,
-                109, 97, 120, 45, 111, 118, 101, 114, 97, 108, 108, 45, 117, 112, 108, 111, 97,
-                100, 45, 108, 105, 109, 105, 116, 61, 48, 10, 109, 97, 120, 45, 117, 112, 108, 111,
-                97, 100, 45, 108, 105, 109, 105, 116, 61, 48, 10, 100, 105, 114, 61, 47, 85, 115,
-                101, 114, 115, 47, 98, 114, 117, 99, 101, 122, 104, 111, 117, 47, 109, 111, 118,
-                105, 101, 10, 102, 105, 108, 101, 45, 97, 108, 108, 111, 99, 97, 116, 105, 111,
-                110, 61, 112, 114, 101, 97, 108, 108, 111, 99, 13, 10,
-            ]
-        )
+            FormData::parse(b"simple", data).unwrap_err().to_string(),
+            "Http Error multipart boundary mismatch"
+        );
+        let data = b"--simple\r\n\r\ndata\r\n--simpl";
+        assert!(FormData::parse(b"simple", data).is_err());
+    }
+
+    #[test]
+    fn test_form_data_parse_malformed() {
+        let inputs: [&[u8]; 4] = [
+            b"--b\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\nx\r\n--b\r\n",
+            b"--b\r\nContent-Disposition: form-data; name=\r\n\r\nx\r\n--b--\r\n",
+            b"--b\r\nContent-Disposition: form-data; name=\"\r\n\r\nx\r\n--b--\r\n",
+            b"--b\r\nContent-Disposition: form-data; name=\"\xe4\xbd\xa0\r\n\r\nx\r\n--b--\r\n",
+        ];
+        for input in inputs.iter() {
+            let _ = FormData::parse(b"b", input);
+        }
     }
 }
@@ -0,0 +1,260 @@
+use std::io::{self, Read};
+
+use super::FormDataPart;
+use crate::infra::http::Error;
+
+const BUFFER_SIZE: usize = 16 * 1024;
+/// part headers are buffered in memory, so their size is capped
+const MAX_HEADER_SIZE: usize = 16 * 1024;
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Preamble,
+    Delimiter,
+    Body,
+    End,
+}
+
+fn invalid(message: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, Error::new(message))
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Reads a multipart body part by part without holding it in memory, see RFC 2046 section 5.1.1
+pub struct MultipartReader<R: Read> {
+    reader: R,
+    buffer: Vec<u8>,
+    eof: bool,
+    delimiter: Vec<u8>,
+    state: State,
+}
+
+/// One part of a multipart body, its headers are parsed and its data is read on demand
+pub struct Part<'a, R: Read> {
+    pub info: FormDataPart,
+    reader: &'a mut MultipartReader<R>,
+}
+
+impl<'a, R: Read> Read for Part<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.reader.read_body(buf)
+    }
+}
+
+impl<R: Read> MultipartReader<R> {
+    pub fn new(reader: R, boundary: &[u8]) -> Self {
+        let mut delimiter = Vec::from(&b"\r\n--"[..]);
+        delimiter.extend_from_slice(boundary);
+        Self {
+            reader,
+            // the leading CRLF lets the first boundary match without a preamble
+            buffer: Vec::from(&b"\r\n"[..]),
+            eof: false,
+            delimiter,
+            state: State::Preamble,
+        }
+    }
+    /// reads more input into the buffer, returns false once the input is exhausted
+    fn fill(&mut self) -> io::Result<bool> {
+        if self.eof {
+            return Ok(false);
+        }
+        let start = self.buffer.len();
+        self.buffer.resize(start + BUFFER_SIZE, 0);
+        let size = loop {
+            match self.reader.read(&mut self.buffer[start..]) {
+                Ok(size) => break size,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.buffer.truncate(start);
+                    return Err(err);
+                }
+            }
+        };
+        self.buffer.truncate(start + size);
+        self.eof = size == 0;
+        Ok(size > 0)
+    }
+    fn fill_to(&mut self, size: usize) -> io::Result<bool> {
+        while self.buffer.len() < size {
+            if !self.fill()? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+    fn read_body(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.state != State::Body || buf.is_empty() {
+            return Ok(0);
+        }
+        loop {
+            // bytes that cannot be the start of a delimiter are safe to hand out
+            let safe = match find(&self.buffer, &self.delimiter) {
+                Some(0) => {
+                    self.buffer.drain(..self.delimiter.len());
+                    self.state = State::Delimiter;
+                    return Ok(0);
+                }
+                Some(index) => index,
+                None => self
+                    .buffer
+                    .len()
+                    .saturating_sub(self.delimiter.len() - 1),
+            };
+            if safe > 0 {
+                let size = safe.min(buf.len());
+                buf[..size].copy_from_slice(&self.buffer[..size]);
+                self.buffer.drain(..size);
+                return Ok(size);
+            }
+            if !self.fill()? {
+                return Err(invalid("missing closing multipart boundary"));
+            }
+        }
+    }
+    fn skip_preamble(&mut self) -> io::Result<()> {
+        loop {
+            if let Some(index) = find(&self.buffer, &self.delimiter) {
+                self.buffer.drain(..index + self.delimiter.len());
+                self.state = State::Delimiter;
+                return Ok(());
+            }
+            let keep = self.delimiter.len() - 1;
+            if self.buffer.len() > keep {
+                self.buffer.drain(..self.buffer.len() - keep);
+            }
+            if !self.fill()? {
+                return Err(invalid("multipart boundary mismatch"));
+            }
+        }
+    }
+    fn read_headers(&mut self) -> io::Result<FormDataPart> {
+        // a part without headers starts with the blank line right away
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"\r\n") {
+            self.buffer.drain(..2);
+            return Ok(FormDataPart::new());
+        }
+        let end = loop {
+            if let Some(end) = find(&self.buffer, b"\r\n\r\n") {
+                break end;
+            }
+            if self.buffer.len() > MAX_HEADER_SIZE {
+                return Err(invalid("multipart headers too large"));
+            }
+            if !self.fill()? {
+                return Err(invalid("malformed multipart headers"));
+            }
+        };
+        let mut part = FormDataPart::new();
+        for line in self.buffer[..end].split(|byte| *byte == b'\n') {
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line =
+                std::str::from_utf8(line).map_err(|_| invalid("malformed multipart header"))?;
+            let (header_field, header_value) = line
+                .split_once(':')
+                .ok_or_else(|| invalid("malformed multipart header"))?;
+            part.apply_header(header_field.trim(), header_value.trim());
+        }
+        self.buffer.drain(..end + 4);
+        Ok(part)
+    }
+    /// Moves to the next part, skipping whatever is left of the current one
+    pub fn next_part(&mut self) -> io::Result<Option<Part<'_, R>>> {
+        if self.state == State::Preamble {
+            self.skip_preamble()?;
+        }
+        let mut buf = [0_u8; BUFFER_SIZE];
+        while self.state == State::Body {
+            self.read_body(&mut buf)?;
+        }
+        if self.state == State::End {
+            return Ok(None);
+        }
+
+        // after a delimiter comes either `--` closing the body or transport padding and CRLF
+        self.fill_to(2)?;
+        if self.buffer.starts_with(b"--") {
+            // anything after the close delimiter is epilogue
+            self.state = State::End;
+            return Ok(None);
+        }
+        loop {
+            let padding = self
+                .buffer
+                .iter()
+                .take_while(|byte| **byte == b' ' || **byte == b'\t')
+                .count();
+            self.buffer.drain(..padding);
+            if !self.fill_to(2)? {
+                return Err(invalid("malformed multipart boundary line"));
+            }
+            if self.buffer[0] != b' ' && self.buffer[0] != b'\t' {
+                break;
+            }
+        }
+        if !self.buffer.starts_with(b"\r\n") {
+            return Err(invalid("malformed multipart boundary line"));
+        }
+        self.buffer.drain(..2);
+
+        let info = self.read_headers()?;
+        self.state = State::Body;
+        Ok(Some(Part { info, reader: self }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read};
+
+    use super::MultipartReader;
+
+    /// hands out one byte per read so delimiters are split across reads
+    struct Trickle<'a>(&'a [u8]);
+
+    impl<'a> Read for Trickle<'a> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() || buf.is_empty() {
+                return Ok(0);
+            }
+            buf[0] = self.0[0];
+            self.0 = &self.0[1..];
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn read_parts_incrementally() {
+        let body = b"preamble\r\n--xyz\r\nContent-Disposition: form-data; name=\"a\"\r\n\r\n\r\n--xy\r\n--xyz\r\nContent-Disposition: form-data; name=\"b\"; filename=\"b.bin\"\r\n\r\nsecond\r\n--xyz--\r\nepilogue";
+        let mut reader = MultipartReader::new(Trickle(body), b"xyz");
+
+        let mut part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.info.name.as_deref(), Some("a"));
+        let mut data = Vec::new();
+        part.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"\r\n--xy");
+
+        let part = reader.next_part().unwrap().unwrap();
+        assert_eq!(part.info.name.as_deref(), Some("b"));
+        assert_eq!(part.info.filename.as_deref(), Some("b.bin"));
+
+        // the unread rest of "b" is skipped
+        assert!(reader.next_part().unwrap().is_none());
+        assert!(reader.next_part().unwrap().is_none());
+    }
+
+    #[test]
+    fn read_truncated_part() {
+        let body = b"--xyz\r\n\r\nno closing boundary";
+        let mut reader = MultipartReader::new(Trickle(body), b"xyz");
+        let mut part = reader.next_part().unwrap().unwrap();
+        let mut data = Vec::new();
+        assert!(part.read_to_end(&mut data).is_err());
+    }
+}
@@ -0,0 +1,223 @@
+/// Splits a header value like `multipart/form-data; boundary="abc"` into its
+/// leading value and its parameters, parameter names are lowercased
+pub fn parse_params(value: &str) -> (String, Vec<(String, String)>) {
+    let mut chars = value.chars().peekable();
+    let mut main = String::new();
+    while let Some(c) = chars.next_if(|c| *c != ';') {
+        main.push(c);
+    }
+
+    let mut params = Vec::new();
+    while chars.next().is_some() {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|c| *c != '=' && *c != ';') {
+            name.push(c);
+        }
+        let name = name.trim().to_ascii_lowercase();
+        let mut param = String::new();
+        if chars.next_if_eq(&'=').is_some() {
+            while chars.next_if(|c| *c == ' ' || *c == '\t').is_some() {}
+            if chars.next_if_eq(&'"').is_some() {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => param.extend(chars.next()),
+                        c => param.push(c),
+                    }
+                }
+                while chars.next_if(|c| *c != ';').is_some() {}
+            } else {
+                while let Some(c) = chars.next_if(|c| *c != ';') {
+                    param.push(c);
+                }
+                param = String::from(param.trim_end());
+            }
+        }
+        if !name.is_empty() {
+            params.push((name, param));
+        }
+    }
+    (String::from(main.trim()), params)
+}
+
+pub fn get_param<'a>(params: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    params
+        .iter()
+        .find(|(key, _)| key.eq_ignore_ascii_case(name))
+        .map(|(_, value)| value.as_str())
+}
+
+/// Wraps `value` in a quoted-string, CR and LF cannot be escaped so they are percent-encoded
+pub fn quote(value: &str) -> String {
+    let mut quoted = String::from("\"");
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            '\r' => quoted.push_str("%0D"),
+            '\n' => quoted.push_str("%0A"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Encodes `value` as an RFC 5987 ext-value in UTF-8, the inverse of `decode_ext_value`
+pub fn encode_ext_value(value: &str) -> String {
+    let mut encoded = String::from("UTF-8''");
+    for byte in value.bytes() {
+        // attr-char, see RFC 5987 section 3.2.1
+        if byte.is_ascii_alphanumeric() || b"!#$&+-.^_`|~".contains(&byte) {
+            encoded.push(char::from(byte));
+        } else {
+            encoded.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    encoded
+}
+
+/// Decodes an RFC 5987 ext-value like `UTF-8''%E2%82%AC%20rates`, as used by `filename*`
+pub fn decode_ext_value(value: &str) -> Option<String> {
+    let mut fields = value.splitn(3, '\'');
+    let charset = fields.next()?;
+    let _language = fields.next()?;
+    let encoded = fields.next()?.as_bytes();
+
+    let mut bytes = Vec::with_capacity(encoded.len());
+    let mut index = 0;
+    while index < encoded.len() {
+        if encoded[index] == b'%' {
+            let hex = std::str::from_utf8(encoded.get(index + 1..index + 3)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            index += 3;
+        } else {
+            bytes.push(encoded[index]);
+            index += 1;
+        }
+    }
+    if charset.eq_ignore_ascii_case("UTF-8") {
+        String::from_utf8(bytes).ok()
+    } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+        Some(bytes.into_iter().map(char::from).collect())
+    } else {
+        None
+    }
+}
+
+/// The answer to a `Range` header, see RFC 9110 section 14.2
+#[derive(Debug, PartialEq)]
+pub enum ByteRange {
+    /// bytes `start..=end` of the representation
+    Satisfiable(u64, u64),
+    /// none of the asked bytes exist
+    Unsatisfiable,
+}
+
+/// Parses a single byte range against a representation of `length` bytes.
+/// `None` means the header is ignored: another unit, several ranges or invalid syntax.
+pub fn parse_range(value: &str, length: u64) -> Option<ByteRange> {
+    let spec = value.trim().strip_prefix("bytes=")?.trim();
+    if spec.contains(',') {
+        return None;
+    }
+    let (first, last) = spec.split_once('-')?;
+    let (first, last) = (first.trim(), last.trim());
+    let (start, end) = if first.is_empty() {
+        // `-500` asks for the last 500 bytes
+        let suffix: u64 = last.parse().ok()?;
+        if suffix == 0 || length == 0 {
+            return Some(ByteRange::Unsatisfiable);
+        }
+        (length.saturating_sub(suffix), length - 1)
+    } else {
+        let start: u64 = first.parse().ok()?;
+        let end: u64 = match last {
+            "" => u64::MAX,
+            last => last.parse().ok()?,
+        };
+        if end < start {
+            return None;
+        }
+        (start, end.min(length.saturating_sub(1)))
+    };
+    if start >= length {
+        return Some(ByteRange::Unsatisfiable);
+    }
+    Some(ByteRange::Satisfiable(start, end))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        decode_ext_value, encode_ext_value, get_param, parse_params, parse_range, quote, ByteRange,
+    };
+
+    #[test]
+    fn parse_byte_ranges() {
+        assert_eq!(parse_range("bytes=0-9", 100), Some(ByteRange::Satisfiable(0, 9)));
+        assert_eq!(parse_range("bytes=90-", 100), Some(ByteRange::Satisfiable(90, 99)));
+        assert_eq!(parse_range("bytes=-30", 100), Some(ByteRange::Satisfiable(70, 99)));
+        assert_eq!(parse_range("bytes=-300", 100), Some(ByteRange::Satisfiable(0, 99)));
+        assert_eq!(parse_range("bytes=50-500", 100), Some(ByteRange::Satisfiable(50, 99)));
+        assert_eq!(parse_range("bytes=100-", 100), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-", 0), Some(ByteRange::Unsatisfiable));
+        assert_eq!(parse_range("bytes=0-1,5-9", 100), None);
+        assert_eq!(parse_range("bytes=9-0", 100), None);
+        assert_eq!(parse_range("items=0-9", 100), None);
+    }
+
+    #[test]
+    fn parse_content_type_params() {
+        let (value, params) = parse_params("multipart/form-data; boundary=----abc");
+        assert_eq!(value, "multipart/form-data");
+        assert_eq!(get_param(&params, "boundary"), Some("----abc"));
+
+        let (value, params) =
+            parse_params("multipart/form-data ;Boundary=\"a;b \\\"c\\\"\"; charset = utf-8 ");
+        assert_eq!(value, "multipart/form-data");
+        assert_eq!(get_param(&params, "boundary"), Some("a;b \"c\""));
+        assert_eq!(get_param(&params, "charset"), Some("utf-8"));
+    }
+
+    #[test]
+    fn parse_params_without_value() {
+        let (value, params) = parse_params("attachment; inline;; name=");
+        assert_eq!(value, "attachment");
+        assert_eq!(get_param(&params, "inline"), Some(""));
+        assert_eq!(get_param(&params, "name"), Some(""));
+        assert_eq!(get_param(&params, "missing"), None);
+    }
+
+    #[test]
+    fn decode_ext_values() {
+        assert_eq!(
+            decode_ext_value("UTF-8''%E2%82%AC%20rates.txt").as_deref(),
+            Some("€ rates.txt")
+        );
+        assert_eq!(
+            decode_ext_value("utf-8'en'%E6%96%87%E4%BB%B6").as_deref(),
+            Some("文件")
+        );
+        assert_eq!(decode_ext_value("iso-8859-1''%A3").as_deref(), Some("£"));
+        assert_eq!(decode_ext_value("UTF-8''%FF"), None);
+        assert_eq!(decode_ext_value("UTF-8''%4"), None);
+        assert_eq!(decode_ext_value("UTF-16''a"), None);
+        assert_eq!(decode_ext_value("no quotes"), None);
+    }
+
+    #[test]
+    fn quote_and_encode_round_trip() {
+        let value = "a;b \"c\" \\d\r\n";
+        let (_, params) = parse_params(&format!("form-data; name={}", quote(value)));
+        assert_eq!(get_param(&params, "name"), Some("a;b \"c\" \\d%0D%0A"));
+
+        let value = "€ rates; 文件.txt";
+        assert_eq!(
+            decode_ext_value(&encode_ext_value(value)).as_deref(),
+            Some(value)
+        );
+    }
+}
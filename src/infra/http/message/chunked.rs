@@ -0,0 +1,59 @@
+use std::io::{self, Write};
+
+/// Frames everything written to it with the chunked transfer coding, see RFC 9112 section 7.1
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self { inner }
+    }
+    /// Writes the last chunk, without it the client treats the body as cut off
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // an empty chunk would end the body
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write!(self.inner, "{:x}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufWriter, Write};
+
+    use super::ChunkedWriter;
+
+    #[test]
+    fn write_chunks() {
+        let mut writer = ChunkedWriter::new(Vec::new());
+        writer.write_all(b"hello ").unwrap();
+        writer.write_all(b"").unwrap();
+        writer.write_all(b"chunked world").unwrap();
+        assert_eq!(
+            writer.finish().unwrap(),
+            b"6\r\nhello \r\nd\r\nchunked world\r\n0\r\n\r\n"
+        );
+
+        let mut writer = BufWriter::new(ChunkedWriter::new(Vec::new()));
+        writer.write_all(b"a").unwrap();
+        writer.write_all(b"b").unwrap();
+        let writer = writer.into_inner().ok().unwrap();
+        assert_eq!(writer.finish().unwrap(), b"2\r\nab\r\n0\r\n\r\n");
+    }
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use super::{find_header, Refused};
+use crate::infra::http::status;
+
+/// Checks a header line that decides where the body ends, before it is stored
+pub fn check_header(headers: &HashMap<String, String>, field: &str) -> Result<(), Refused> {
+    if field.eq_ignore_ascii_case("Content-Length") && find_header(headers, field).is_some() {
+        return Err(Refused::new(status::BAD_REQUEST, "duplicate Content-Length"));
+    }
+    Ok(())
+}
+
+/// Finds the body length once the head is complete. Anything but a single plain
+/// `Content-Length` could be read differently by a proxy in front, so it is refused.
+pub fn content_length(headers: &HashMap<String, String>) -> Result<u64, Refused> {
+    let content_length = find_header(headers, "Content-Length");
+    if find_header(headers, "Transfer-Encoding").is_some() {
+        if content_length.is_some() {
+            return Err(Refused::new(
+                status::BAD_REQUEST,
+                "Transfer-Encoding with Content-Length",
+            ));
+        }
+        return Err(Refused::new(
+            status::NOT_IMPLEMENTED,
+            "unsupported Transfer-Encoding",
+        ));
+    }
+    match content_length {
+        None => Ok(0),
+        Some(content_length) => content_length
+            .bytes()
+            .all(|byte| byte.is_ascii_digit())
+            .then(|| content_length.parse().ok())
+            .flatten()
+            .ok_or(Refused::new(status::BAD_REQUEST, "invalid Content-Length")),
+    }
+}
@@ -3,51 +3,53 @@ use rust_fsm::*;
 state_machine! {
     derive(Debug,PartialEq)
     pub RequestMessage(End)
-    End(Alpha) => Method[EffectAppendMethod],
+    End(Alpha) => Method[AppendMethod],
     Method => {
-        Alpha => Method[EffectAppendMethod],
+        Alpha => Method[AppendMethod],
         Blank => Blank0
     },
-    Blank0(Alpha) => Path[EffectAppendPath],
+    Blank0(Alpha) => Path[AppendPath],
     Path => {
-        Alpha => Path[EffectAppendPath],
+        Alpha => Path[AppendPath],
         Blank => Blank1
     },
-    Blank1(Alpha) => Version[EffectAppendVersion],
+    Blank1(Alpha) => Version[AppendVersion],
     Version => {
-        Alpha => Version[EffectAppendVersion],
+        Alpha => Version[AppendVersion],
         Cr => Cr0
     },
     Cr0(Lf) => Lf0,
-    Lf0(Alpha) => HeaderField[EffectAppendHeaderField],
+    Lf0 => {
+        Alpha => HeaderField[AppendHeaderField],
+        Cr => Cr2
+    },
     HeaderField => {
-        Alpha => HeaderField[EffectAppendHeaderField],
+        Alpha => HeaderField[AppendHeaderField],
         Colon => Colon0,
     },
-    Colon0(Blank) => Blank2,
-    Blank2(Alpha) => HeaderValue[EffectAppendHeaderValue],
+    Colon0 => {
+        Blank => Blank2,
+        Alpha => HeaderValue[AppendHeaderValue],
+        Colon => HeaderValue[AppendHeaderValue],
+        Cr => Cr1[AppendHeader]
+    },
+    Blank2 => {
+        Blank => Blank2,
+        Alpha => HeaderValue[AppendHeaderValue],
+        Colon => HeaderValue[AppendHeaderValue],
+        Cr => Cr1[AppendHeader]
+    },
     HeaderValue => {
-        Alpha => HeaderValue[EffectAppendHeaderValue],
-        Blank => HeaderValue[EffectAppendHeaderValue],
-        Colon => HeaderValue[EffectAppendHeaderValue],
-        Cr => Cr1[EffectAppendHeader]
+        Alpha => HeaderValue[AppendHeaderValue],
+        Blank => HeaderValue[AppendHeaderValue],
+        Colon => HeaderValue[AppendHeaderValue],
+        Cr => Cr1[AppendHeader]
     },
     Cr1(Lf) => Lf1,
     Lf1 => {
-        Alpha => HeaderField[EffectAppendHeaderField],
+        Alpha => HeaderField[AppendHeaderField],
         Cr => Cr2
     },
-    Cr2(Lf) => Lf2[EffectCheckEnd],
-    Lf2 => {
-        Alpha => Body[EffectAppendBody],
-        End => End,
-    },
-    Body => {
-        Alpha => Body[EffectAppendBody],
-        Blank => Body[EffectAppendBody],
-        Colon => Body[EffectAppendBody],
-        Cr => Body[EffectAppendBody],
-        Lf => Body[EffectAppendBody],
-        End => End
-    }
+    Cr2(Lf) => Lf2[CheckEnd],
+    Lf2(End) => End
 }
@@ -1,18 +1,78 @@
+mod chunked;
+mod framing;
 mod fsm;
+mod strict;
 
 use rust_fsm::StateMachine;
 use std::{
-    cell::RefCell,
+    cell::{Cell, RefCell},
     collections::HashMap,
-    io::{Read, Write},
+    fmt,
+    io::{self, BufWriter, Read, Write},
     rc::Rc,
     sync::Arc,
+    time::Duration,
 };
 
+use self::chunked::ChunkedWriter;
+use super::url::Url;
+
 pub type HandleFn = Box<Arc<dyn Fn(Rc<RefCell<Request>>) -> Response + Send + Sync>>;
 
+/// Called with the request head when the client sent `Expect: 100-continue`,
+/// returning a response rejects the request before its body is read
+pub type ExpectFn = Box<Arc<dyn Fn(&Request) -> Option<Response> + Send + Sync>>;
+
 const HTTP_VERSION: &str = "1.1";
 
+/// chunks of a streamed body are buffered up to this size before they are sent
+const STREAM_BUFFER_SIZE: usize = 64 * 1024;
+
+/// unread body bytes skipped to keep a connection alive, larger leftovers close it instead
+const MAX_DRAIN_SIZE: u64 = 64 * 1024;
+
+/// how long an idle connection waits for the next request, so idle clients do not hold a worker
+const KEEP_ALIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// how long each read may take once a request has started, a slow upload still makes progress
+const READ_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A connection whose reads give up after a while, see `KEEP_ALIVE_TIMEOUT` and `READ_TIMEOUT`
+pub trait ReadTimeout {
+    fn set_read_timeout(&self, _timeout: Option<Duration>) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// whether a read failed because its timeout ran out, sockets report it as `WouldBlock` on Unix
+fn is_timeout(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+    )
+}
+
+/// A request refused before it reaches the handler, answered with `code` and the connection closed
+#[derive(Debug)]
+struct Refused {
+    code: super::status::Status,
+    message: &'static str,
+}
+
+impl Refused {
+    fn new(code: super::status::Status, message: &'static str) -> Self {
+        Self { code, message }
+    }
+}
+
+impl fmt::Display for Refused {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Http Error {}", self.message)
+    }
+}
+
+impl std::error::Error for Refused {}
+
 pub trait HttpMessage {
     fn get_header(&self, key: &str) -> Option<&str>;
     fn set_header(&mut self, key: &str, value: &str);
@@ -22,29 +82,161 @@ pub trait HttpMessage {
 pub struct Request {
     pub method: String,
     pub path: String,
+    pub url: Url,
     pub version: String,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
+    /// the body still on the connection, left for the handler to read or stream
+    pub body_reader: Option<BodyReader>,
+}
+
+/// Reads a request body of known length straight from the connection
+#[derive(Clone)]
+pub struct BodyReader {
+    connection: Rc<RefCell<dyn Read>>,
+    remaining: Rc<Cell<u64>>,
+    timed_out: Rc<Cell<bool>>,
+}
+
+impl BodyReader {
+    /// `length` bytes of `connection`, the rest is left for the next request
+    pub fn new(connection: Rc<RefCell<dyn Read>>, length: u64) -> Self {
+        Self {
+            connection,
+            remaining: Rc::new(Cell::new(length)),
+            timed_out: Rc::new(Cell::new(false)),
+        }
+    }
+    pub fn remaining(&self) -> u64 {
+        self.remaining.get()
+    }
+    /// whether the client stopped sending the body, whatever the handler made of the error
+    pub fn timed_out(&self) -> bool {
+        self.timed_out.get()
+    }
+}
+
+impl Read for BodyReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.remaining.get();
+        if remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let size = buf.len().min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let size = match self.connection.borrow_mut().read(&mut buf[..size]) {
+            Ok(size) => size,
+            Err(err) if is_timeout(&err) => {
+                self.timed_out.set(true);
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    "timed out reading the request body",
+                ));
+            }
+            Err(err) => return Err(err),
+        };
+        if size == 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "connection closed before the request body was complete",
+            ));
+        }
+        self.remaining.set(remaining - size as u64);
+        Ok(size)
+    }
+}
+
+impl std::fmt::Debug for BodyReader {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("BodyReader")
+            .field("remaining", &self.remaining.get())
+            .finish()
+    }
+}
+
+impl Request {
+    /// Reads whatever is left of the body into `body`
+    pub fn read_body(&mut self) -> io::Result<&Vec<u8>> {
+        if let Some(mut body_reader) = self.body_reader.take() {
+            body_reader.read_to_end(&mut self.body)?;
+        }
+        Ok(&self.body)
+    }
+    /// whether the connection stays open after this request, see RFC 9112 section 9.3
+    pub fn keep_alive(&self) -> bool {
+        let connection = self.get_header("Connection").unwrap_or("");
+        let has_option = |option: &str| {
+            connection
+                .split(',')
+                .any(|item| item.trim().eq_ignore_ascii_case(option))
+        };
+        if self.version == "HTTP/1.0" {
+            has_option("keep-alive")
+        } else {
+            !has_option("close")
+        }
+    }
 }
 
-impl Request {}
+/// header names are case-insensitive, see RFC 9110 section 5.1
+fn find_header<'a>(headers: &'a HashMap<String, String>, key: &str) -> Option<&'a str> {
+    if let Some(value) = headers.get(key) {
+        return Some(value.as_str());
+    }
+    headers
+        .iter()
+        .find(|(field, _)| field.eq_ignore_ascii_case(key))
+        .map(|(_, value)| value.as_str())
+}
 
 impl HttpMessage for Request {
     fn get_header(&self, key: &str) -> Option<&str> {
-        Some(self.headers.get(key)?.as_str())
+        find_header(&self.headers, key)
     }
     fn set_header(&mut self, key: &str, value: &str) {
         self.headers.insert(String::from(key), String::from(value));
     }
 }
 
+type WriteFn = Box<dyn FnOnce(&mut dyn Write) -> io::Result<()>>;
+
+/// A response body produced while it is sent, for content too large or too slow to build up front
+#[derive(Clone)]
+pub struct BodyStream(Rc<RefCell<Option<WriteFn>>>);
+
+impl BodyStream {
+    pub fn new(write: impl FnOnce(&mut dyn Write) -> io::Result<()> + 'static) -> Self {
+        Self(Rc::new(RefCell::new(Some(Box::new(write)))))
+    }
+    /// Runs the writer, a stream is only written once
+    fn write_to(&self, writer: &mut dyn Write) -> io::Result<()> {
+        match self.0.borrow_mut().take() {
+            Some(write) => write(writer),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for BodyStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("BodyStream")
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Response {
     pub version: String,
     pub code: u16,
     pub headers: HashMap<String, String>,
     pub body: Vec<u8>,
+    /// sent instead of `body` when set, chunked unless the client speaks HTTP/1.0
+    pub stream: Option<BodyStream>,
 }
+impl Default for Response {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Response {
     pub fn new() -> Self {
         Self {
@@ -52,6 +244,7 @@ impl Response {
             body: Vec::new(),
             code: super::status::OK,
             headers: HashMap::new(),
+            stream: None,
         }
     }
     pub fn with_text(code: super::status::Status, text: &str) -> Response {
@@ -61,6 +254,16 @@ impl Response {
         response
     }
     pub fn to_bytes(&self) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        self.encode(true)
+    }
+    /// Encodes the head and `body`, a streamed body is left out and announced as chunked if `chunked`
+    fn encode(&self, chunked: bool) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+        let mut bytes = self.encode_head(chunked)?;
+        bytes.extend_from_slice(&self.body);
+        Ok(bytes)
+    }
+    /// Encodes the status line and headers, announcing the body that `encode` would send
+    fn encode_head(&self, chunked: bool) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
         use std::fmt::Write;
         let mut bytes_msg = String::new();
         bytes_msg.write_fmt(format_args!(
@@ -72,19 +275,29 @@ impl Response {
         for (key, value) in &self.headers {
             bytes_msg.write_fmt(format_args!("{}: {}\r\n", key, value))?;
         }
-        bytes_msg.write_fmt(format_args!("\r\n"))?;
-        let mut body = self.body.clone();
-        unsafe {
-            bytes_msg.as_mut_vec().append(&mut body);
+        // persistent connections rely on the length to find the next response
+        let has_body = !(self.code < 200
+            || self.code == super::status::NO_CONTENT
+            || self.code == super::status::NOT_MODIFIED);
+        if has_body && self.get_header("Content-Length").is_none() {
+            if self.stream.is_none() {
+                bytes_msg.write_fmt(format_args!("Content-Length: {}\r\n", self.body.len()))?;
+            } else if chunked {
+                bytes_msg.write_fmt(format_args!("Transfer-Encoding: chunked\r\n"))?;
+            }
         }
-        Ok(bytes_msg.as_bytes().to_vec())
+        bytes_msg.write_fmt(format_args!("\r\n"))?;
+        Ok(bytes_msg.into_bytes())
     }
-    pub fn set_body(&mut self, body: &Vec<u8>) {
-        self.body = body.clone();
+    pub fn set_body(&mut self, body: &[u8]) {
+        self.body = body.to_vec();
     }
     pub fn set_code(&mut self, code: u16) {
         self.code = code;
     }
+    pub fn set_stream(&mut self, stream: BodyStream) {
+        self.stream = Some(stream);
+    }
 }
 
 impl HttpMessage for Response {
@@ -96,100 +309,160 @@ impl HttpMessage for Response {
     }
 }
 
-struct Parser<'a, T: Read> {
-    readable: &'a mut T,
+struct Parser<T: Read + Write + ReadTimeout + 'static> {
+    connection: Rc<RefCell<T>>,
     machine: StateMachine<fsm::RequestMessage>,
+    on_expect: Option<ExpectFn>,
+    /// reject anything RFC 9112 does not allow instead of guessing, see `strict.rs`
+    strict: bool,
 }
 
-impl<'a, T: Read> Parser<'a, T> {
-    fn new(readable: &mut T) -> Parser<T> {
+impl<T: Read + Write + ReadTimeout + 'static> Parser<T> {
+    fn new(connection: Rc<RefCell<T>>, on_expect: Option<ExpectFn>) -> Parser<T> {
         Parser {
-            readable,
+            connection,
             machine: StateMachine::new(),
+            on_expect,
+            strict: false,
         }
     }
+    /// Answers an `Expect` header before the body is read, returns false if the request was rejected
+    fn check_expect(&mut self, request: &Request) -> Result<bool, Box<dyn std::error::Error>> {
+        let expect = match request.get_header("Expect") {
+            Some(expect) => expect,
+            None => return Ok(true),
+        };
+        let response = if !expect.eq_ignore_ascii_case("100-continue") {
+            Some(Response::with_text(
+                super::status::EXPECTATION_FAILED,
+                "unsupported expectation",
+            ))
+        } else {
+            match &self.on_expect {
+                Some(on_expect) => on_expect(request),
+                None => None,
+            }
+        };
+        let accepted = response.is_none();
+        let response = response.unwrap_or_else(|| {
+            let mut response = Response::new();
+            response.set_code(super::status::CONTINUE);
+            response
+        });
+        let mut connection = self.connection.borrow_mut();
+        connection.write_all(&response.to_bytes()?)?;
+        connection.flush()?;
+        Ok(accepted)
+    }
+    /// Parses the next request head, its body is left on the connection behind `body_reader`
     fn parse(&mut self) -> Result<Option<Request>, Box<dyn std::error::Error>> {
-        let mut body: Vec<u8> = Vec::new();
         let mut headers: HashMap<String, String> = HashMap::new();
         let mut header_field = String::new();
         let mut header_value = String::new();
         let mut method = String::new();
         let mut path = Vec::new();
         let mut version = String::new();
-        let mut rest_body_size = 0_u64;
 
         let mut byte = [0_u8; 1];
+        let mut started = false;
+        self.connection
+            .borrow()
+            .set_read_timeout(Some(KEEP_ALIVE_TIMEOUT))?;
         loop {
-            if let Err(_) = self.readable.read_exact(&mut byte) {
-                return Ok(None);
-            };
+            let read = self.connection.borrow_mut().read_exact(&mut byte);
+            match read {
+                Ok(()) => {}
+                // a client that went quiet mid head is told so, an idle one is just closed
+                Err(err) if started && is_timeout(&err) => {
+                    return Err(Box::new(Refused::new(
+                        super::status::REQUEST_TIMEOUT,
+                        "request timeout",
+                    )));
+                }
+                Err(_) => return Ok(None),
+            }
+            if !started {
+                started = true;
+                self.connection.borrow().set_read_timeout(Some(READ_TIMEOUT))?;
+            }
             let byte = byte[0];
 
-            let effect = if rest_body_size == 0
-                && (self.machine.state() == &fsm::RequestMessageState::Lf2
-                    || self.machine.state() == &fsm::RequestMessageState::Body)
-            {
-                self.machine.consume(&fsm::RequestMessageInput::End)
-            } else {
-                match byte {
-                    b' ' => self.machine.consume(&fsm::RequestMessageInput::Blank),
-                    b':' => self.machine.consume(&fsm::RequestMessageInput::Colon),
-                    b'\r' => self.machine.consume(&fsm::RequestMessageInput::Cr),
-                    b'\n' => self.machine.consume(&fsm::RequestMessageInput::Lf),
-                    _ => self.machine.consume(&fsm::RequestMessageInput::Alpha),
+            if self.strict {
+                let state = self.machine.state();
+                if state == &fsm::RequestMessageState::Lf1 && (byte == b' ' || byte == b'\t') {
+                    return Err(Box::new(super::Error::new("obs-fold in header")));
+                }
+                if byte == b'\n'
+                    && state != &fsm::RequestMessageState::Cr0
+                    && state != &fsm::RequestMessageState::Cr1
+                    && state != &fsm::RequestMessageState::Cr2
+                {
+                    return Err(Box::new(super::Error::new("bare LF")));
                 }
+            }
+
+            let effect = match byte {
+                b' ' => self.machine.consume(&fsm::RequestMessageInput::Blank),
+                b':' => self.machine.consume(&fsm::RequestMessageInput::Colon),
+                b'\r' => self.machine.consume(&fsm::RequestMessageInput::Cr),
+                b'\n' => self.machine.consume(&fsm::RequestMessageInput::Lf),
+                _ => self.machine.consume(&fsm::RequestMessageInput::Alpha),
             };
 
             match effect? {
                 Some(effect) => match effect {
-                    fsm::RequestMessageOutput::EffectAppendHeader => {
-                        headers.insert(header_field.clone(), header_value.clone());
+                    fsm::RequestMessageOutput::AppendHeader => {
+                        let value = header_value.trim_end_matches([' ', '\t']);
+                        if self.strict {
+                            strict::check_header(&headers, &header_field, value)?;
+                        }
+                        framing::check_header(&headers, &header_field)?;
+                        headers.insert(header_field.clone(), String::from(value));
                         header_field.clear();
                         header_value.clear();
                     }
-                    fsm::RequestMessageOutput::EffectAppendHeaderField => {
+                    fsm::RequestMessageOutput::AppendHeaderField => {
                         header_field.push(char::from(byte));
                     }
-                    fsm::RequestMessageOutput::EffectAppendHeaderValue => {
+                    fsm::RequestMessageOutput::AppendHeaderValue => {
                         header_value.push(char::from(byte));
                     }
-                    fsm::RequestMessageOutput::EffectAppendMethod => {
+                    fsm::RequestMessageOutput::AppendMethod => {
                         method.push(char::from(byte));
                     }
-                    fsm::RequestMessageOutput::EffectAppendPath => {
+                    fsm::RequestMessageOutput::AppendPath => {
                         path.push(byte);
                     }
-                    fsm::RequestMessageOutput::EffectAppendVersion => {
+                    fsm::RequestMessageOutput::AppendVersion => {
                         version.push(char::from(byte));
                     }
-                    _ => {
-                        match effect {
-                            fsm::RequestMessageOutput::EffectCheckEnd => {
-                                if let Some(content_length) = headers.get("Content-Length") {
-                                    rest_body_size = content_length.parse().unwrap_or(0);
-                                }
-                            }
-                            fsm::RequestMessageOutput::EffectAppendBody => {
-                                body.push(byte);
-                                rest_body_size -= 1;
-                            }
-                            _ => {}
+                    fsm::RequestMessageOutput::CheckEnd => {
+                        self.machine.consume(&fsm::RequestMessageInput::End)?;
+                        if self.strict {
+                            strict::check_head(&method, &version, &headers)?;
                         }
-                        if rest_body_size == 0 {
-                            use urlencoding::decode_binary;
-                            let request = super::message::Request {
-                                body,
-                                headers,
-                                method,
-                                path: String::from_utf8(decode_binary(&path.to_owned()).to_vec())?,
-                                version,
-                            };
-                            self.machine.consume(&fsm::RequestMessageInput::End)?;
-                            return Ok(Some(request));
+                        let content_length = framing::content_length(&headers)?;
+                        let url = Url::parse(&path)?;
+                        let mut request = Request {
+                            body: Vec::new(),
+                            body_reader: None,
+                            headers,
+                            method,
+                            path: url.path(),
+                            url,
+                            version,
+                        };
+                        if content_length > 0 {
+                            if !self.check_expect(&request)? {
+                                return Ok(None);
+                            }
+                            request.body_reader =
+                                Some(BodyReader::new(self.connection.clone(), content_length));
                         }
+                        return Ok(Some(request));
                     }
                 },
-                _ => {
+                None => {
                     // do nothing
                 }
             }
@@ -197,45 +470,153 @@ impl<'a, T: Read> Parser<'a, T> {
     }
 }
 
-pub fn consume<T: Write + Read>(
-    connection: &mut T,
-    on_data: HandleFn,
+pub fn consume<T: Write + Read + ReadTimeout + 'static>(
+    connection: Rc<RefCell<T>>,
+    on_data: &HandleFn,
+    on_expect: Option<ExpectFn>,
+    strict: bool,
 ) -> Result<(), Box<dyn std::error::Error>> {
-    let mut parser = Parser::new(connection);
-    if let Some(request) = parser.parse().unwrap() {
-        let response = on_data(Rc::new(RefCell::new(request)));
-        connection.write_all(&response.to_bytes().unwrap())?;
+    let mut parser = Parser::new(connection.clone(), on_expect);
+    parser.strict = strict;
+    // requests are handled one after another, so pipelined responses keep their order
+    loop {
+        let mut chunked = true;
+        let mut head = false;
+        let (mut response, mut keep_alive) = match parser.parse() {
+            Ok(Some(request)) => {
+                let keep_alive = request.keep_alive();
+                chunked = request.version != "HTTP/1.0";
+                head = request.method == "HEAD";
+                let body_reader = request.body_reader.clone();
+                let response = on_data(Rc::new(RefCell::new(request)));
+                // skip what the handler left of the body so the next request starts in the right place
+                match body_reader {
+                    Some(body_reader) if body_reader.timed_out() => (
+                        Response::with_text(super::status::REQUEST_TIMEOUT, "request timeout"),
+                        false,
+                    ),
+                    Some(mut body_reader) if body_reader.remaining() > 0 => {
+                        if body_reader.remaining() > MAX_DRAIN_SIZE {
+                            (response, false)
+                        } else {
+                            match io::copy(&mut body_reader, &mut io::sink()) {
+                                Ok(_) => (response, keep_alive),
+                                // the client went away mid body, nothing more can follow
+                                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                                    (response, false)
+                                }
+                                Err(err) if err.kind() == io::ErrorKind::TimedOut => (
+                                    Response::with_text(
+                                        super::status::REQUEST_TIMEOUT,
+                                        "request timeout",
+                                    ),
+                                    false,
+                                ),
+                                Err(err) => return Err(err.into()),
+                            }
+                        }
+                    }
+                    _ => (response, keep_alive),
+                }
+            }
+            Ok(None) => return Ok(()),
+            Err(err) => {
+                let code = err
+                    .downcast_ref::<Refused>()
+                    .map_or(super::status::BAD_REQUEST, |refused| refused.code);
+                (Response::with_text(code, &err.to_string()), false)
+            }
+        };
+        // without chunks, the end of a streamed body can only be told by closing the connection
+        let has_length = response.get_header("Content-Length").is_some();
+        if response.stream.is_some() && !chunked && !has_length && !head {
+            keep_alive = false;
+        }
+        if !keep_alive {
+            response.set_header("Connection", "close");
+        }
+        let mut connection = connection.borrow_mut();
+        connection.write_all(&response.encode_head(chunked)?)?;
+        // a HEAD response announces the body of the GET but never sends it, see RFC 9110 section 9.3.2
+        let stream = if head {
+            None
+        } else {
+            connection.write_all(&response.body)?;
+            response.stream.take()
+        };
+        match stream {
+            Some(stream) if chunked && !has_length => {
+                let mut writer =
+                    BufWriter::with_capacity(STREAM_BUFFER_SIZE, ChunkedWriter::new(&mut *connection));
+                stream.write_to(&mut writer)?;
+                writer.into_inner().map_err(|err| err.into_error())?.finish()?;
+            }
+            Some(stream) => {
+                let mut writer = BufWriter::with_capacity(STREAM_BUFFER_SIZE, &mut *connection);
+                stream.write_to(&mut writer)?;
+                writer.flush()?;
+            }
+            None => connection.flush()?,
+        }
+        if !keep_alive {
+            return Ok(());
+        }
     }
-    Ok(())
 }
 
 #[cfg(test)]
 mod tests {
     use std::{
+        cell::RefCell,
         cmp,
         io::{self, Write},
+        rc::Rc,
+        sync::Arc,
     };
 
     use crate::infra::http::{
-        message::{HttpMessage, Parser},
+        message::{
+            consume, BodyStream, ExpectFn, HandleFn, HttpMessage, Parser, ReadTimeout, Request,
+            Response,
+        },
         method::{get_methods, Method},
+        status,
     };
     struct StringStream {
         data: Vec<u8>,
         index: usize,
+        written: Vec<u8>,
+        /// the end of `data` reads as a timeout instead of a closed connection
+        stalls: bool,
     }
     impl StringStream {
-        fn new(data: &'static str) -> Self {
-            Self {
+        fn new(data: &'static str) -> Rc<RefCell<Self>> {
+            Rc::new(RefCell::new(Self {
                 data: Vec::from(data),
                 index: 0,
-            }
+                written: Vec::new(),
+                stalls: false,
+            }))
+        }
+    }
+
+    impl ReadTimeout for StringStream {}
+
+    impl io::Write for StringStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.written.write(buf)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
         }
     }
 
     impl io::Read for StringStream {
         fn read(&mut self, mut buf: &mut [u8]) -> io::Result<usize> {
             let size = cmp::min(buf.len(), self.data.len() - self.index);
+            if size == 0 && self.stalls && !buf.is_empty() {
+                return Err(io::ErrorKind::WouldBlock.into());
+            }
             let result = buf.write(&self.data[self.index..(self.index + size)]);
             self.index += size;
             result
@@ -252,8 +633,8 @@ mod tests {
 
     #[test]
     fn parse_request_get() {
-        let mut readable = StringStream::new("GET / HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\n\r\n");
-        let mut parser = Parser::new(&mut readable);
+        let readable = StringStream::new("GET / HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\n\r\n");
+        let mut parser = Parser::new(readable.clone(), None);
         let request = parser.parse().unwrap().unwrap();
         assert_eq!(get_methods(request.method.as_str()).unwrap(), Method::Get);
         assert_eq!(request.path, "/");
@@ -274,9 +655,9 @@ mod tests {
     /// ```
     #[test]
     fn parse_request_post() {
-        let mut readable = StringStream::new("POST /user HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\nContent-Type: application/json\r\nContent-Length: 23\r\n\r\n{\"name\":\"tom\",\"age\":21}");
-        let mut parser = Parser::new(&mut readable);
-        let request = parser.parse().unwrap().unwrap();
+        let readable = StringStream::new("POST /user HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nUser-Agent: curl/7.64.1\r\nAccept: */*\r\nContent-Type: application/json\r\nContent-Length: 23\r\n\r\n{\"name\":\"tom\",\"age\":21}");
+        let mut parser = Parser::new(readable.clone(), None);
+        let mut request = parser.parse().unwrap().unwrap();
         assert_eq!(get_methods(request.method.as_str()).unwrap(), Method::Post);
         assert_eq!(request.path, "/user");
         assert_eq!(request.get_header("Host").unwrap(), "127.0.0.1:3000");
@@ -287,6 +668,249 @@ mod tests {
             "application/json"
         );
         assert_eq!(request.get_header("Content-Length").unwrap(), "23");
-        assert_eq!(request.body, Vec::from("{\"name\":\"tom\",\"age\":21}"));
+        assert_eq!(
+            request.read_body().unwrap(),
+            &Vec::from("{\"name\":\"tom\",\"age\":21}")
+        );
+    }
+
+    #[test]
+    fn parse_request_query() {
+        let readable = StringStream::new("GET /dir%2Fname/file.txt?download=1 HTTP/1.1\r\nHost: 127.0.0.1:3000\r\n\r\n");
+        let mut parser = Parser::new(readable.clone(), None);
+        let request = parser.parse().unwrap().unwrap();
+        assert_eq!(request.path, "/dir/name/file.txt");
+        assert_eq!(request.url.segments.len(), 2);
+        assert_eq!(request.url.query.get("download"), Some("1"));
+    }
+
+    #[test]
+    fn parse_request_fragment() {
+        let readable = StringStream::new("GET /index.html#top HTTP/1.1\r\nHost: 127.0.0.1:3000\r\n\r\n");
+        let mut parser = Parser::new(readable.clone(), None);
+        assert!(parser.parse().is_err());
+    }
+
+    #[test]
+    fn parse_request_expect_continue() {
+        let readable = StringStream::new("POST /upload HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+        let mut parser = Parser::new(readable.clone(), None);
+        let mut request = parser.parse().unwrap().unwrap();
+        assert_eq!(readable.borrow().written, Vec::from("HTTP/1.1 100 Continue\r\n\r\n"));
+        assert_eq!(request.read_body().unwrap(), &Vec::from("hello"));
+    }
+
+    #[test]
+    fn parse_request_expect_rejected() {
+        let readable = StringStream::new("POST /upload HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nExpect: 100-continue\r\nContent-Length: 5\r\n\r\nhello");
+        let on_expect: ExpectFn = Box::new(Arc::new(|request: &Request| {
+            assert_eq!(request.get_header("Content-Length"), Some("5"));
+            Some(Response::with_text(status::PAYLOAD_TOO_LARGE, "too large"))
+        }));
+        let mut parser = Parser::new(readable.clone(), Some(on_expect));
+        assert!(parser.parse().unwrap().is_none());
+        assert!(readable.borrow().written.starts_with(b"HTTP/1.1 413 Payload Too Large\r\n"));
+        assert!(readable.borrow().written.ends_with(b"too large"));
+    }
+
+    #[test]
+    fn consume_pipelined_requests() {
+        let readable = StringStream::new("GET /first HTTP/1.1\r\nHost: 127.0.0.1:3000\r\n\r\nPOST /second HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nContent-Length: 4\r\n\r\nbodyGET /third HTTP/1.1\r\nHost: 127.0.0.1:3000\r\nConnection: close\r\n\r\nGET /ignored HTTP/1.1\r\n\r\n");
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            let mut request = request.borrow_mut();
+            let body = String::from_utf8_lossy(request.read_body().unwrap()).into_owned();
+            Response::with_text(status::OK, &format!("{}{}", request.path, body))
+        }));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        let bodies: Vec<&str> = written
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|response| response.rsplit("\r\n").next().unwrap())
+            .collect();
+        assert_eq!(bodies, vec!["/first", "/secondbody", "/third"]);
+        assert_eq!(written.matches("Connection: close").count(), 1);
+    }
+
+    #[test]
+    fn keep_alive_by_version() {
+        let readable = StringStream::new("GET / HTTP/1.0\r\nHost: 127.0.0.1:3000\r\n\r\nGET / HTTP/1.0\r\nConnection: Keep-Alive\r\n\r\n");
+        let mut parser = Parser::new(readable.clone(), None);
+        assert!(!parser.parse().unwrap().unwrap().keep_alive());
+        assert!(parser.parse().unwrap().unwrap().keep_alive());
+    }
+
+    #[test]
+    fn parse_request_optional_whitespace() {
+        let readable = StringStream::new("GET / HTTP/1.1\r\nHost:127.0.0.1:3000\r\nAccept:   */*  \r\nX-Empty:\r\n\r\n");
+        let mut parser = Parser::new(readable.clone(), None);
+        let request = parser.parse().unwrap().unwrap();
+        assert_eq!(request.get_header("host").unwrap(), "127.0.0.1:3000");
+        assert_eq!(request.get_header("Accept").unwrap(), "*/*");
+        assert_eq!(request.get_header("X-Empty").unwrap(), "");
+    }
+
+    fn parse_strict(data: &'static str) -> Result<Option<Request>, String> {
+        let readable = StringStream::new(data);
+        let mut parser = Parser::new(readable.clone(), None);
+        parser.strict = true;
+        parser.parse().map_err(|err| err.to_string())
+    }
+
+    #[test]
+    fn parse_request_strict() {
+        assert!(parse_strict("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 2\r\n\r\nok")
+            .unwrap()
+            .is_some());
+        let rejected = [
+            ("GET / HTTP/1.1\r\n\r\n", "missing Host"),
+            ("G(T / HTTP/1.1\r\nHost: a\r\n\r\n", "invalid method"),
+            ("GET / HTTP/2.0\r\nHost: a\r\n\r\n", "unsupported version"),
+            ("GET / HTTP/1.1x\r\nHost: a\r\n\r\n", "invalid version"),
+            ("GET / HTTP/1.1\r\nHost: a\nAccept: */*\r\n\r\n", "bare LF"),
+            ("GET / HTTP/1.1\r\nHost: a\r\nX-Long: a\r\n b\r\n\r\n", "obs-fold in header"),
+            ("GET / HTTP/1.1\r\nHost: a\r\nX\"Bad: a\r\n\r\n", "invalid header field name"),
+            ("GET / HTTP/1.1\r\nHost: a\r\nHost: b\r\n\r\n", "duplicate Host"),
+        ];
+        for (data, reason) in rejected.iter() {
+            assert_eq!(
+                parse_strict(data).unwrap_err(),
+                format!("Http Error {}", reason),
+                "{:?}",
+                data
+            );
+        }
+    }
+
+    #[test]
+    fn parse_request_framing() {
+        let rejected = [
+            ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\ncontent-length: 2\r\n\r\nab", "duplicate Content-Length"),
+            ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1x\r\n\r\n", "invalid Content-Length"),
+            ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: +1\r\n\r\n", "invalid Content-Length"),
+            ("POST / HTTP/1.1\r\nHost: a\r\nContent-Length:\r\n\r\n", "invalid Content-Length"),
+            ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\nContent-Length: 3\r\n\r\n0\r\n\r\n", "Transfer-Encoding with Content-Length"),
+            ("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\n", "unsupported Transfer-Encoding"),
+        ];
+        // framing is never guessed, whether strict or not
+        for (data, reason) in rejected.iter() {
+            let readable = StringStream::new(data);
+            let mut parser = Parser::new(readable.clone(), None);
+            assert_eq!(
+                parser.parse().unwrap_err().to_string(),
+                format!("Http Error {}", reason),
+                "{:?}",
+                data
+            );
+            assert_eq!(parse_strict(data).unwrap_err(), format!("Http Error {}", reason));
+        }
+    }
+
+    #[test]
+    fn consume_refuses_smuggled_request() {
+        let readable = StringStream::new("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n0\r\n\r\nGET /smuggled HTTP/1.1\r\nHost: a\r\n\r\n");
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            Response::with_text(status::OK, &request.borrow().path)
+        }));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        assert_eq!(written.matches("HTTP/1.1 ").count(), 1);
+        assert!(written.starts_with("HTTP/1.1 501 Not Implemented\r\n"));
+        assert!(written.contains("Connection: close\r\n"));
+        assert!(!written.contains("/smuggled"));
+    }
+
+    #[test]
+    fn parse_request_body_leading_whitespace() {
+        let readable = StringStream::new("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\n\r\n: ");
+        let mut parser = Parser::new(readable.clone(), None);
+        let mut request = parser.parse().unwrap().unwrap();
+        assert_eq!(request.read_body().unwrap(), &Vec::from("\r\n: "));
+    }
+
+    #[test]
+    fn consume_skips_unread_body() {
+        let readable = StringStream::new("POST /first HTTP/1.1\r\nHost: a\r\nContent-Length: 4\r\n\r\nbodyGET /second HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n");
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            Response::with_text(status::OK, &request.borrow().path)
+        }));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        assert!(written.contains("\r\n\r\n/first"));
+        assert!(written.ends_with("\r\n\r\n/second"));
+    }
+
+    #[test]
+    fn consume_truncated_body() {
+        let readable = StringStream::new("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nab");
+        let on_data: HandleFn = Box::new(Arc::new(|_| Response::with_text(status::OK, "ok")));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        assert!(written.contains("Connection: close"));
+    }
+
+    #[test]
+    fn consume_head_without_body() {
+        let readable = StringStream::new("HEAD /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n");
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            Response::with_text(status::OK, &request.borrow().path)
+        }));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        let responses: Vec<&str> = written.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        assert_eq!(responses.len(), 2);
+        assert!(responses[0].contains("Content-Length: 2\r\n"));
+        assert!(responses[0].ends_with("\r\n\r\n"));
+        assert!(!responses[0].contains("Connection: close"));
+        assert!(responses[1].ends_with("\r\n\r\n/b"));
+    }
+
+    #[test]
+    fn consume_timeouts() {
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            let mut request = request.borrow_mut();
+            match request.read_body() {
+                Ok(_) => Response::with_text(status::OK, "ok"),
+                Err(err) => Response::with_text(status::BAD_REQUEST, &err.to_string()),
+            }
+        }));
+        let stalled = |data: &'static str| {
+            let readable = StringStream::new(data);
+            readable.borrow_mut().stalls = true;
+            consume(readable.clone(), &on_data, None, false).unwrap();
+            let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+            written
+        };
+        // idle between requests
+        assert_eq!(stalled("GET / HTTP/1.1\r\nHost: a\r\n\r\n").matches("HTTP/1.1 ").count(), 1);
+        // mid head and mid body
+        for data in ["GET / HTTP/1.1\r\nHost: a\r\n", "POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nab"] {
+            let written = stalled(data);
+            assert!(written.starts_with("HTTP/1.1 408 Request Timeout\r\n"), "{:?}", data);
+            assert!(written.contains("Connection: close\r\n"));
+        }
+    }
+
+    #[test]
+    fn consume_streamed_body() {
+        let readable = StringStream::new("GET /a HTTP/1.1\r\nHost: a\r\n\r\nGET /b HTTP/1.0\r\nHost: a\r\n\r\n");
+        let on_data: HandleFn = Box::new(Arc::new(|request| {
+            let path = request.borrow().path.clone();
+            let mut response = Response::new();
+            response.set_stream(BodyStream::new(move |writer| {
+                writer.write_all(b"streamed ")?;
+                writer.write_all(path.as_bytes())
+            }));
+            response
+        }));
+        consume(readable.clone(), &on_data, None, false).unwrap();
+        let written = String::from_utf8(readable.borrow().written.clone()).unwrap();
+        let responses: Vec<&str> = written.split("HTTP/1.1 200 OK\r\n").skip(1).collect();
+        let (first, second) = (responses[0], responses[1]);
+        assert!(first.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(first.ends_with("\r\n\r\nb\r\nstreamed /a\r\n0\r\n\r\n"));
+        assert!(!second.contains("Transfer-Encoding"));
+        assert!(second.contains("Connection: close\r\n"));
+        assert!(second.ends_with("\r\n\r\nstreamed /b"));
     }
 }
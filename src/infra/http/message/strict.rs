@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+
+use super::find_header;
+use crate::infra::http::Error;
+
+/// tchar, see RFC 9110 section 5.6.2
+fn is_token_char(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte)
+}
+
+fn is_token(value: &str) -> bool {
+    !value.is_empty() && value.bytes().all(is_token_char)
+}
+
+/// Checks a single header line before it is stored
+pub fn check_header(
+    headers: &HashMap<String, String>,
+    field: &str,
+    value: &str,
+) -> Result<(), Error> {
+    if !is_token(field) {
+        return Err(Error::new("invalid header field name"));
+    }
+    if value
+        .bytes()
+        .any(|byte| (byte < 0x20 && byte != b'\t') || byte == 0x7f)
+    {
+        return Err(Error::new("invalid header field value"));
+    }
+    if field.eq_ignore_ascii_case("Host") && find_header(headers, field).is_some() {
+        return Err(Error::new("duplicate Host"));
+    }
+    Ok(())
+}
+
+/// Checks the request line and the headers once the head is complete, framing is left to `framing.rs`
+pub fn check_head(
+    method: &str,
+    version: &str,
+    headers: &HashMap<String, String>,
+) -> Result<(), Error> {
+    if !is_token(method) {
+        return Err(Error::new("invalid method"));
+    }
+    let digits = version.as_bytes();
+    if digits.len() != 8
+        || !version.starts_with("HTTP/")
+        || !digits[5].is_ascii_digit()
+        || digits[6] != b'.'
+        || !digits[7].is_ascii_digit()
+    {
+        return Err(Error::new("invalid version"));
+    }
+    if version != "HTTP/1.0" && version != "HTTP/1.1" {
+        return Err(Error::new("unsupported version"));
+    }
+    if version == "HTTP/1.1" && find_header(headers, "Host").is_none() {
+        return Err(Error::new("missing Host"));
+    }
+    Ok(())
+}
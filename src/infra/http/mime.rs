@@ -1,4 +1,9 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fs, io,
+    path::Path,
+    sync::RwLock,
+};
 
 macro_rules! generate_mime {
     ($($name:expr => $content_type:expr),+) => {
@@ -79,10 +84,217 @@ generate_mime! {
     "xul" => "application/vnd.mozilla.xul+xml",
     "zip" => "application/zip",
     "3gp" => "video/3gpp",
+    "c" => "text/plain",
+    "cfg" => "text/plain",
+    "conf" => "text/plain",
+    "h" => "text/plain",
+    "ini" => "text/plain",
+    "log" => "text/plain",
+    "markdown" => "text/markdown",
+    "md" => "text/markdown",
+    "mp4" => "video/mp4",
+    "py" => "text/plain",
+    "rs" => "text/plain",
     "toml" => "text/plain",
-    "rs" => "text/rs"
+    "wasm" => "application/wasm",
+    "yaml" => "text/plain",
+    "yml" => "text/plain"
 }
 
-pub fn get_mime(ext: &str) -> Option<&'static str> {
-    Some(MIME_MAP.get(&ext)?)
+lazy_static! {
+    static ref LOADED: RwLock<Loaded> = RwLock::new(Loaded::default());
+}
+
+#[derive(Default)]
+struct Loaded {
+    overrides: HashMap<String, String>,
+    fallbacks: HashMap<String, String>,
+}
+
+/// Where loaded mappings rank against the built-in ones
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Priority {
+    /// replaces a built-in mapping, for the configuration of this server
+    Override,
+    /// only fills the gaps of the built-in mappings, for the system wide `/etc/mime.types`
+    Fallback,
+}
+
+/// Parses the `mime.types` format, a type followed by its extensions on each line
+///
+/// Example
+///
+/// ```text
+/// # comment
+/// text/markdown   md markdown
+/// ```
+pub fn parse_mime_types(text: &str) -> Vec<(String, String)> {
+    text.lines()
+        .map(|line| line.split('#').next().unwrap_or(""))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            let mime_type = fields.next()?;
+            mime_type.contains('/').then_some((mime_type, fields))
+        })
+        .flat_map(|(mime_type, extensions)| {
+            extensions.map(move |extension| {
+                let extension = extension.trim_start_matches('.').to_ascii_lowercase();
+                (extension, mime_type.to_ascii_lowercase())
+            })
+        })
+        .collect()
+}
+
+pub fn insert(extension: &str, mime_type: &str, priority: Priority) {
+    let mut loaded = LOADED.write().unwrap();
+    let map = match priority {
+        Priority::Override => &mut loaded.overrides,
+        Priority::Fallback => &mut loaded.fallbacks,
+    };
+    map.insert(extension.to_ascii_lowercase(), String::from(mime_type));
+}
+
+/// Adds the mappings of a `mime.types` file, returns how many were read
+pub fn load(path: &Path, priority: Priority) -> io::Result<usize> {
+    let mappings = parse_mime_types(&fs::read_to_string(path)?);
+    for (extension, mime_type) in &mappings {
+        insert(extension, mime_type, priority);
+    }
+    Ok(mappings.len())
+}
+
+/// The media type of a file extension, in any case
+pub fn get_mime(ext: &str) -> Option<String> {
+    let ext = ext.to_ascii_lowercase();
+    let loaded = LOADED.read().unwrap();
+    loaded
+        .overrides
+        .get(&ext)
+        .cloned()
+        .or_else(|| MIME_MAP.get(ext.as_str()).map(|mime_type| String::from(*mime_type)))
+        .or_else(|| loaded.fallbacks.get(&ext).cloned())
+}
+
+/// Bytes expected at an offset of a file
+type Signature = &'static [(usize, &'static [u8])];
+
+/// Signatures of common binary formats
+const MAGIC: &[(Signature, &str)] = &[
+    (&[(0, b"\x89PNG\r\n\x1a\n")], "image/png"),
+    (&[(0, b"\xff\xd8\xff")], "image/jpeg"),
+    (&[(0, b"GIF8")], "image/gif"),
+    (&[(0, b"RIFF"), (8, b"WEBP")], "image/webp"),
+    (&[(0, b"RIFF"), (8, b"WAVE")], "audio/wav"),
+    (&[(0, b"RIFF"), (8, b"AVI ")], "video/x-msvideo"),
+    (&[(0, b"\0\0\x01\0")], "image/vnd.microsoft.icon"),
+    (&[(0, b"II*\0")], "image/tiff"),
+    (&[(0, b"MM\0*")], "image/tiff"),
+    (&[(0, b"%PDF-")], "application/pdf"),
+    (&[(0, b"PK\x03\x04")], "application/zip"),
+    (&[(0, b"\x1f\x8b")], "application/gzip"),
+    (&[(0, b"BZh")], "application/x-bzip2"),
+    (&[(0, b"\xfd7zXZ\0")], "application/x-xz"),
+    (&[(0, b"7z\xbc\xaf\x27\x1c")], "application/x-7z-compressed"),
+    (&[(0, b"\0asm")], "application/wasm"),
+    (&[(0, b"OggS")], "application/ogg"),
+    (&[(0, b"ID3")], "audio/mpeg"),
+    (&[(4, b"ftyp")], "video/mp4"),
+    (&[(0, b"\x1a\x45\xdf\xa3")], "video/webm"),
+    (&[(0, b"wOFF")], "font/woff"),
+    (&[(0, b"wOF2")], "font/woff2"),
+];
+
+/// Guesses the media type from the first bytes of a file whose name does not tell it.
+/// Markup is never guessed, an uploaded file must not turn into a page running scripts.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    let matches = |signature: Signature| {
+        signature
+            .iter()
+            .all(|(offset, bytes)| head.get(*offset..offset + bytes.len()) == Some(*bytes))
+    };
+    if let Some((_, mime_type)) = MAGIC.iter().find(|(signature, _)| matches(signature)) {
+        return Some(mime_type);
+    }
+    // text when it decodes as UTF-8, allowing a character cut at the end of the sample
+    let text = match std::str::from_utf8(head) {
+        Ok(text) => text,
+        Err(err) if err.error_len().is_none() => {
+            std::str::from_utf8(&head[..err.valid_up_to()]).unwrap_or("")
+        }
+        Err(_) => return None,
+    };
+    let binary = text
+        .chars()
+        .any(|c| c.is_control() && !matches!(c, '\t' | '\n' | '\r' | '\x0c' | '\x1b'));
+    (!head.is_empty() && !binary).then_some("text/plain")
+}
+
+/// Adds `charset=utf-8` to text types that do not name their charset
+pub fn with_charset(mime_type: &str) -> String {
+    let is_text = mime_type
+        .get(..5)
+        .is_some_and(|prefix| prefix.eq_ignore_ascii_case("text/"));
+    if is_text && !mime_type.to_ascii_lowercase().contains("charset=") {
+        format!("{}; charset=utf-8", mime_type)
+    } else {
+        String::from(mime_type)
+    }
+}
+
+/// The `Content-Type` of a file, from its extension or else from its first bytes
+pub fn content_type(extension: &str, head: &[u8]) -> Option<String> {
+    let mime_type = get_mime(extension).or_else(|| sniff(head).map(String::from))?;
+    Some(with_charset(&mime_type))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type, get_mime, insert, parse_mime_types, sniff, Priority};
+
+    #[test]
+    fn lookup_ignores_case() {
+        assert_eq!(get_mime("PNG").as_deref(), Some("image/png"));
+        assert_eq!(get_mime("Html").as_deref(), Some("text/html"));
+        assert_eq!(get_mime("nope"), None);
+    }
+
+    #[test]
+    fn loaded_mappings() {
+        let mappings = parse_mime_types(
+            "# comment\ntext/x-test  tst1 .TST2 # trailing\n\napplication/x-empty\nnot-a-type tst3\n",
+        );
+        assert_eq!(
+            mappings,
+            [
+                (String::from("tst1"), String::from("text/x-test")),
+                (String::from("tst2"), String::from("text/x-test")),
+            ]
+        );
+
+        insert("tstfallback", "application/x-fallback", Priority::Fallback);
+        insert("png", "application/x-not-png", Priority::Fallback);
+        insert("tstoverride", "application/x-first", Priority::Fallback);
+        insert("TSTOVERRIDE", "application/x-override", Priority::Override);
+        assert_eq!(get_mime("tstfallback").as_deref(), Some("application/x-fallback"));
+        assert_eq!(get_mime("png").as_deref(), Some("image/png"));
+        assert_eq!(get_mime("tstoverride").as_deref(), Some("application/x-override"));
+    }
+
+    #[test]
+    fn sniff_content() {
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0"), Some("image/png"));
+        assert_eq!(sniff(b"RIFF\0\0\0\0WEBPVP8 "), Some("image/webp"));
+        assert_eq!(sniff(b"\0\0\0\x18ftypmp42"), Some("video/mp4"));
+        assert_eq!(sniff(b"%PDF-1.7"), Some("application/pdf"));
+        assert_eq!(sniff("plain text, \u{4e2d}\u{6587}".as_bytes()), Some("text/plain"));
+        // a multibyte character cut by the end of the sample is still text
+        assert_eq!(sniff(&"ab\u{4e2d}".as_bytes()[..4]), Some("text/plain"));
+        assert_eq!(sniff(b"\x7fELF\x02\x01\x01\0"), None);
+        assert_eq!(sniff(b""), None);
+
+        assert_eq!(content_type("TXT", b"").as_deref(), Some("text/plain; charset=utf-8"));
+        assert_eq!(content_type("", b"\xff\xd8\xff\xe0").as_deref(), Some("image/jpeg"));
+        assert_eq!(content_type("", b"<html><script>").as_deref(), Some("text/plain; charset=utf-8"));
+        assert_eq!(content_type("", b"\0\x01\x02"), None);
+    }
 }
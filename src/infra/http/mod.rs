@@ -1,8 +1,12 @@
+pub mod body;
+pub mod date;
 pub mod form_data;
+pub mod header;
 pub mod message;
 pub mod method;
 pub mod mime;
 pub mod status;
+pub mod url;
 
 #[derive(Debug)]
 pub struct Error {
@@ -0,0 +1,256 @@
+use urlencoding::{decode_binary, encode, encode_binary};
+
+use super::Error;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TargetForm {
+    Origin,
+    Absolute,
+    Asterisk,
+}
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Query {
+    pub pairs: Vec<(String, String)>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// Parses a query string. One that is not UTF-8 is read as ISO-8859-1, which never fails.
+    pub fn parse(raw: &[u8]) -> Self {
+        Query::parse_with_charset(raw, "UTF-8")
+            .or_else(|_| Query::parse_with_charset(raw, "ISO-8859-1"))
+            .unwrap_or_default()
+    }
+    /// Encodes the pairs back into a query string, without the leading `?`
+    pub fn encode(&self) -> String {
+        let pairs: Vec<String> = self
+            .pairs
+            .iter()
+            .map(|(key, value)| format!("{}={}", encode(key), encode(value)))
+            .collect();
+        pairs.join("&")
+    }
+    /// Parses an urlencoded form body, decoding names and values with `charset`
+    pub fn parse_with_charset(raw: &[u8], charset: &str) -> Result<Self, Error> {
+        let decode = |raw: &[u8]| -> Result<String, Error> {
+            let bytes = decode_component_bytes(raw);
+            if charset.eq_ignore_ascii_case("UTF-8") {
+                String::from_utf8(bytes).map_err(|_| Error::new("invalid UTF-8 in form body"))
+            } else if charset.eq_ignore_ascii_case("ISO-8859-1") {
+                Ok(bytes.into_iter().map(char::from).collect())
+            } else {
+                Err(Error::new("unsupported charset"))
+            }
+        };
+        let mut query = Query::new();
+        for pair in raw.split(|byte| *byte == b'&') {
+            if pair.is_empty() {
+                continue;
+            }
+            let mut pair = pair.splitn(2, |byte| *byte == b'=');
+            let key = decode(pair.next().unwrap_or(b""))?;
+            let value = decode(pair.next().unwrap_or(b""))?;
+            query.pairs.push((key, value));
+        }
+        Ok(query)
+    }
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.pairs
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    pub fn get_all(&self, key: &str) -> Vec<&str> {
+        self.pairs
+            .iter()
+            .filter(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+            .collect()
+    }
+}
+
+/// Parsed request target, see RFC 9112 section 3.2
+#[derive(Debug, Clone, PartialEq)]
+pub struct Url {
+    pub form: TargetForm,
+    pub scheme: Option<String>,
+    pub authority: Option<String>,
+    /// path segments, each percent-decoded on its own so an encoded `/` stays inside its segment
+    pub segments: Vec<Vec<u8>>,
+    pub query: Query,
+}
+
+impl Url {
+    pub fn parse(target: &[u8]) -> Result<Self, Error> {
+        if target.contains(&b'#') {
+            return Err(Error::new("fragment in request target"));
+        }
+        if target == b"*" {
+            return Ok(Self {
+                form: TargetForm::Asterisk,
+                scheme: None,
+                authority: None,
+                segments: Vec::new(),
+                query: Query::new(),
+            });
+        }
+
+        let (form, scheme, authority, rest) = if target.starts_with(b"/") {
+            (TargetForm::Origin, None, None, target)
+        } else {
+            let separator = find(target, b"://").ok_or(Error::new("invalid request target"))?;
+            let scheme = &target[..separator];
+            if scheme.is_empty() || !scheme.iter().all(|byte| byte.is_ascii_alphanumeric()) {
+                return Err(Error::new("invalid request target scheme"));
+            }
+            let rest = &target[separator + 3..];
+            let authority_end = rest
+                .iter()
+                .position(|byte| *byte == b'/' || *byte == b'?')
+                .unwrap_or(rest.len());
+            (
+                TargetForm::Absolute,
+                Some(String::from_utf8_lossy(scheme).to_ascii_lowercase()),
+                Some(String::from_utf8_lossy(&rest[..authority_end]).into_owned()),
+                &rest[authority_end..],
+            )
+        };
+
+        let (path, query) = match rest.iter().position(|byte| *byte == b'?') {
+            Some(index) => (&rest[..index], Query::parse(&rest[index + 1..])),
+            None => (rest, Query::new()),
+        };
+        let path = path.strip_prefix(b"/").unwrap_or(path);
+        let segments = path
+            .split(|byte| *byte == b'/')
+            .map(|segment| decode_binary(segment).into_owned())
+            .collect();
+
+        Ok(Self {
+            form,
+            scheme,
+            authority,
+            segments,
+            query,
+        })
+    }
+    /// decoded path for display, non-UTF-8 bytes are replaced
+    pub fn path(&self) -> String {
+        if self.form == TargetForm::Asterisk {
+            return String::from("*");
+        }
+        let segments: Vec<String> = self
+            .segments
+            .iter()
+            .map(|segment| String::from_utf8_lossy(segment).into_owned())
+            .collect();
+        format!("/{}", segments.join("/"))
+    }
+    /// path re-encoded segment by segment, suitable for links
+    pub fn encoded_path(&self) -> String {
+        encode_segments(&self.segments)
+    }
+}
+
+pub fn encode_segments(segments: &[Vec<u8>]) -> String {
+    let segments: Vec<String> = segments
+        .iter()
+        .map(|segment| encode_binary(segment).into_owned())
+        .collect();
+    format!("/{}", segments.join("/"))
+}
+
+fn decode_component_bytes(raw: &[u8]) -> Vec<u8> {
+    let raw: Vec<u8> = raw
+        .iter()
+        .map(|byte| if *byte == b'+' { b' ' } else { *byte })
+        .collect();
+    decode_binary(&raw).into_owned()
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Query, TargetForm, Url};
+
+    #[test]
+    fn parse_origin_form() {
+        let url = Url::parse(b"/a%2Fb/c%3Fd?name=tom&tag=1&tag=2&q=a+b%26c").unwrap();
+        assert_eq!(url.form, TargetForm::Origin);
+        assert_eq!(url.segments, vec![Vec::from("a/b"), Vec::from("c?d")]);
+        assert_eq!(url.path(), "/a/b/c?d");
+        assert_eq!(url.encoded_path(), "/a%2Fb/c%3Fd");
+        assert_eq!(url.query.get("name"), Some("tom"));
+        assert_eq!(url.query.get_all("tag"), vec!["1", "2"]);
+        assert_eq!(url.query.get("q"), Some("a b&c"));
+        assert_eq!(url.query.get("missing"), None);
+    }
+
+    #[test]
+    fn parse_query_with_charset() {
+        let query = Query::parse_with_charset(b"name=%E6%96%87&q=a+b", "utf-8").unwrap();
+        assert_eq!(query.get("name"), Some("文"));
+        assert_eq!(query.get("q"), Some("a b"));
+        let query = Query::parse_with_charset(b"name=%A3", "ISO-8859-1").unwrap();
+        assert_eq!(query.get("name"), Some("£"));
+        assert!(Query::parse_with_charset(b"name=%A3", "utf-8").is_err());
+        assert!(Query::parse_with_charset(b"name=a", "shift_jis").is_err());
+    }
+
+    #[test]
+    fn parse_trailing_slash() {
+        assert_eq!(Url::parse(b"/").unwrap().path(), "/");
+        assert_eq!(Url::parse(b"/dir/").unwrap().path(), "/dir/");
+    }
+
+    #[test]
+    fn parse_absolute_form() {
+        let url = Url::parse(b"https://127.0.0.1:3000/user?id=1").unwrap();
+        assert_eq!(url.form, TargetForm::Absolute);
+        assert_eq!(url.scheme.as_deref(), Some("https"));
+        assert_eq!(url.authority.as_deref(), Some("127.0.0.1:3000"));
+        assert_eq!(url.path(), "/user");
+        assert_eq!(url.query.get("id"), Some("1"));
+        assert_eq!(Url::parse(b"http://example.com").unwrap().path(), "/");
+    }
+
+    #[test]
+    fn parse_asterisk_form() {
+        let url = Url::parse(b"*").unwrap();
+        assert_eq!(url.form, TargetForm::Asterisk);
+        assert_eq!(url.path(), "*");
+    }
+
+    #[test]
+    fn parse_non_utf8() {
+        let url = Url::parse(b"/%FF%FEname").unwrap();
+        assert_eq!(url.segments, vec![vec![0xff, 0xfe, b'n', b'a', b'm', b'e']]);
+        assert_eq!(url.path(), "/\u{fffd}\u{fffd}name");
+    }
+
+    #[test]
+    fn reject_invalid_target() {
+        assert!(Url::parse(b"/index.html#top").is_err());
+        assert!(Url::parse(b"index.html").is_err());
+        assert!(Url::parse(b"1ht tp://host/").is_err());
+    }
+
+    #[test]
+    fn encode_query() {
+        let query = Query::parse(b"sort=name&q=a+b%26c&empty=");
+        assert_eq!(query.encode(), "sort=name&q=a%20b%26c&empty=");
+        assert_eq!(Query::parse(query.encode().as_bytes()), query);
+        // not UTF-8, still every pair is kept
+        let query = Query::parse(b"sort=name&q=caf%E9");
+        assert_eq!(query.get("sort"), Some("name"));
+        assert_eq!(query.get("q"), Some("caf\u{e9}"));
+    }
+}
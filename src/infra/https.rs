@@ -1,12 +1,25 @@
 use crate::infra;
-use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod};
+use openssl::ssl::{SslAcceptor, SslFiletype, SslMethod, SslStream};
 use std::{
-    net::TcpListener,
+    cell::RefCell,
+    io,
+    net::{TcpListener, TcpStream},
+    rc::Rc,
     sync::mpsc::{channel, SendError, Sender},
+    time::Duration,
 };
 use threadpool::ThreadPool;
 
-use crate::infra::http::message::HandleFn;
+use crate::infra::http::message::{ExpectFn, HandleFn, ReadTimeout};
+
+/// the TLS handshake runs on the accepting thread, a client stalling in it holds up every other
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl ReadTimeout for SslStream<TcpStream> {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.get_ref().set_read_timeout(timeout)
+    }
+}
 
 #[derive(Debug)]
 pub enum HttpsServerStatus {
@@ -21,22 +34,34 @@ pub struct HttpsServer {
     pub bind_addr: Option<String>,
     pub cert: Option<String>,
     pub key: Option<String>,
+    pub strict: bool,
     pub status: HttpsServerStatus,
     tx: Option<Sender<()>>,
 }
 
+impl Default for HttpsServer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl HttpsServer {
     pub fn new() -> Self {
         Self {
             bind_addr: None,
             cert: None,
             key: None,
+            strict: false,
             status: HttpsServerStatus::Stopped,
             tx: None,
         }
     }
     // launch
-    pub fn launch(&mut self, on_request: HandleFn) -> Result<(), Box<dyn std::error::Error>> {
+    pub fn launch(
+        &mut self,
+        on_request: HandleFn,
+        on_expect: Option<ExpectFn>,
+    ) -> Result<(), Box<dyn std::error::Error>> {
         assert!(self.tx.is_none());
         self.status = HttpsServerStatus::Starting;
         let (tx, rx) = channel();
@@ -51,6 +76,7 @@ impl HttpsServer {
                 .clone()
                 .ok_or(infra::http::Error::new("no bind_addr"))?,
         );
+        let strict = self.strict;
         std::thread::spawn(move || {
             // 创建线程池
             let pool = ThreadPool::new(num_cpus::get());
@@ -73,22 +99,40 @@ impl HttpsServer {
                     return;
                 }
 
+                // 握手超时后放弃，避免阻塞监听线程；之后的超时由http层按阶段设置
+                let connection = connection.unwrap();
+                if let Err(err) = connection.set_read_timeout(Some(HANDSHAKE_TIMEOUT)) {
+                    eprintln!("{:?}", err);
+                    continue;
+                }
+
                 // TCP数据先经SSL层处理再传递给http层
-                let connection = acceptor.accept(connection.unwrap());
+                let connection = acceptor.accept(connection);
 
                 let on_request = on_request.clone();
+                let on_expect = on_expect.clone();
                 pool.execute(move || {
                     if connection.is_err() {
                         eprintln!("{:?}", connection.err());
                         return;
                     }
-                    let mut connection = connection.unwrap();
+                    let connection = Rc::new(RefCell::new(connection.unwrap()));
 
-                    // 从TCP流中提取HTTP报文并交给on_request处理后返回
-                    infra::http::message::consume(&mut connection, on_request).unwrap();
+                    // 从TCP流中依次提取HTTP报文并交给on_request处理后返回
+                    if let Err(err) = infra::http::message::consume(
+                        connection.clone(),
+                        &on_request,
+                        on_expect,
+                        strict,
+                    ) {
+                        eprintln!("{:?}", err);
+                    }
 
                     // 关闭连接
-                    connection.shutdown().unwrap();
+                    let shutdown = connection.borrow_mut().shutdown();
+                    if let Err(err) = shutdown {
+                        eprintln!("{:?}", err);
+                    }
                 })
             }
         });
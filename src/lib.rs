@@ -0,0 +1,4 @@
+#[macro_use]
+extern crate lazy_static;
+
+pub mod infra;
@@ -1,9 +1,7 @@
-#[macro_use]
-extern crate lazy_static;
-
 mod app;
 mod cmd;
-mod infra;
+
+use https_server_app::infra;
 
 fn main() {
     cmd::boost();